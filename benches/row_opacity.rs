@@ -0,0 +1,70 @@
+// Compares compute with a per-tile opacity closure against compute_row_opacity
+// copying row slices out of the same Matrix. Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use shadowcast::{INITIAL_VISIBILITY, Matrix, Point, Vision, VisionArgs};
+
+const RADIUS: i32 = 40;
+const ITERATIONS: u32 = 200;
+
+// A map with scattered pillars and partially-transparent grass, so that casts
+// split into many beams, as on real levels.
+fn generate_map() -> Matrix<i32> {
+    let size = Point(2 * RADIUS + 41, 2 * RADIUS + 41);
+    let mut map = Matrix::new(size, INITIAL_VISIBILITY);
+    let mut state = 0x2545f491u32;
+    for x in map.data.iter_mut() {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        *x = match state % 32 {
+            0 => INITIAL_VISIBILITY,
+            1..=4 => 10,
+            _ => 0,
+        };
+    }
+    map
+}
+
+fn time(name: &str, mut f: impl FnMut()) {
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS { f(); }
+    let elapsed = start.elapsed() / ITERATIONS;
+    println!("{:<16} {:>10.1?} per cast", name, elapsed);
+}
+
+fn main() {
+    let map = generate_map();
+    let eye = Point(map.size.0 / 2, map.size.1 / 2);
+    let mut vision = Vision::new(RADIUS);
+
+    for dir in [Point::default(), Point(1, 0)] {
+        println!("dir = {:?}", dir);
+        let args = VisionArgs::new(eye, |p: Point| map.get(p)).with_dir(dir);
+        time("per-tile", || {
+            vision.compute(&args);
+            black_box(vision.get_points_seen().len());
+        });
+
+        let row_fetch = |start: Point, step: Point, out: &mut [i32]| {
+            let end = Point(start.0 + step.0 * (out.len() as i32 - 1), start.1);
+            if step == Point(1, 0) && map.contains(start) && map.contains(end) {
+                let index = map.index(start).unwrap();
+                out.copy_from_slice(&map.data[index..index + out.len()]);
+                return;
+            }
+            let mut point = start;
+            for x in out {
+                *x = map.get(point);
+                point = point + step;
+            }
+        };
+        time("row-fetch", || {
+            vision.compute_row_opacity(eye, dir, row_fetch, INITIAL_VISIBILITY);
+            black_box(vision.get_points_seen().len());
+        });
+    }
+}
@@ -1,11 +1,14 @@
 use std::cmp::{max, min};
 use std::ops::{Add, Sub};
 
+use crate::shadowcast::Transform;
+
 //////////////////////////////////////////////////////////////////////////////
 
 // Point
 
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Point(pub i32, pub i32);
 
 impl Point {
@@ -13,6 +16,34 @@ impl Point {
         (self.0 as i64 * other.0 as i64) + (self.1 as i64 * other.1 as i64)
     }
 
+    pub fn cross(&self, other: Point) -> i64 {
+        (self.0 as i64 * other.1 as i64) - (self.1 as i64 * other.0 as i64)
+    }
+
+    pub fn is_adjacent8(&self, other: Point) -> bool {
+        (*self - other).len_l1() == 1
+    }
+
+    pub fn step_toward(&self, other: Point) -> Point {
+        let Point(x, y) = other - *self;
+        Point(x.signum(), y.signum())
+    }
+
+    // The Bresenham line from this point to other, including both endpoints.
+    pub fn line_to(&self, other: Point) -> Vec<Point> {
+        let (dx, dy) = ((other.0 - self.0).abs(), -(other.1 - self.1).abs());
+        let step = self.step_toward(other);
+        let (mut current, mut error) = (*self, dx + dy);
+        let mut result = vec![current];
+        while current != other {
+            let e2 = 2 * error;
+            if e2 >= dy { error += dy; current.0 += step.0; }
+            if e2 <= dx { error += dx; current.1 += step.1; }
+            result.push(current);
+        }
+        result
+    }
+
     pub fn in_l2_range(&self, range: i32) -> bool {
         self.len_l2() <= range as f64 - 0.5
     }
@@ -39,7 +70,27 @@ impl Point {
         x * x + y * y
     }
 
+    pub fn dist2(&self, other: Point) -> i64 {
+        (other - *self).len_l2_squared()
+    }
+
+    pub fn distance(&self, other: Point, metric: RadiusMetric) -> f64 {
+        let delta = other - *self;
+        match metric {
+            RadiusMetric::Euclidean => delta.len_l2(),
+            RadiusMetric::Chebyshev => delta.len_l1() as f64,
+            RadiusMetric::Taxicab => delta.len_taxicab() as f64,
+            RadiusMetric::Nethack => delta.len_nethack() as f64,
+        }
+    }
+
+    // The method form of t * self, as in p.transform(Transform::ROT_90).
+    pub fn transform(&self, t: Transform) -> Point {
+        t.apply(*self)
+    }
+
     pub fn normalize(&self, length: f64) -> Point {
+        if self.len_l2_squared() == 0 { return Point::default(); }
         let factor = length / self.len_l2();
         let x = (self.0 as f64 * factor).round() as i32;
         let y = (self.1 as f64 * factor).round() as i32;
@@ -61,10 +112,84 @@ impl Sub for Point {
     }
 }
 
+// The cover target has from shooter: the max cover over the tiles next to
+// target on the shooter's side, namely the first step of the line from
+// target to shooter and the orthogonal steps toward the shooter. Doesn't
+// check line of sight; use Vision::can_see for that.
+pub fn cover_between<C: Fn(Point) -> u8>(shooter: Point, target: Point, cover: C) -> u8 {
+    let Point(dx, dy) = shooter - target;
+    let line = target.line_to(shooter);
+    let steps = [Point(dx.signum(), 0), Point(0, dy.signum())];
+    let candidates = line.get(1).copied().into_iter().chain(steps.map(|x| target + x));
+    let candidates = candidates.filter(|&p| p != target && p != shooter);
+    candidates.map(cover).max().unwrap_or(0)
+}
+
+// The distance metrics that Point supports. Euclidean ranges use the same
+// disc rule as the shadowcast, x^2 + y^2 <= r^2 + r, for rounder circles.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum RadiusMetric {
+    #[default]
+    Euclidean,
+    Chebyshev,
+    Taxicab,
+    Nethack,
+}
+
+impl RadiusMetric {
+    pub fn within(&self, delta: Point, range: i32) -> bool {
+        match self {
+            RadiusMetric::Euclidean => {
+                let r = range as i64;
+                delta.len_l2_squared() <= r * r + r
+            }
+            RadiusMetric::Chebyshev => delta.len_l1() <= range,
+            RadiusMetric::Taxicab => delta.len_taxicab() <= range,
+            RadiusMetric::Nethack => delta.len_nethack() <= range,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+// Polygons
+
+// Twice the signed area of the polygon: positive if its vertices are in the
+// order that takes +x toward +y.
+pub fn polygon_area2(poly: &[Point]) -> i64 {
+    let edges = poly.iter().zip(poly.iter().cycle().skip(1));
+    edges.map(|(a, b)| a.cross(*b)).sum()
+}
+
+// Even-odd containment test. Results for points exactly on an edge depend on
+// the edge's orientation.
+pub fn polygon_contains(poly: &[Point], p: Point) -> bool {
+    let edges = poly.iter().zip(poly.iter().cycle().skip(1));
+    edges.fold(false, |inside, (&a, &b)| {
+        if (a.1 > p.1) == (b.1 > p.1) { return inside; }
+        let side = (b - a).cross(p - a);
+        if (b.1 > a.1) == (side > 0) { !inside } else { inside }
+    })
+}
+
 //////////////////////////////////////////////////////////////////////////////
 
 // Matrix
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SizeMismatch {
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl std::fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {} elements, got {}", self.expected, self.actual)
+    }
+}
+
+impl std::error::Error for SizeMismatch {}
+
 #[derive(Clone, Default)]
 pub struct Matrix<T> {
     pub data: Vec<T>,
@@ -78,10 +203,61 @@ impl<T: Clone> Matrix<T> {
         assert!(0 <= size.0);
         assert!(0 <= size.1);
         let mut data = Vec::new();
-        data.resize((size.0 * size.1) as usize, value.clone());
+        data.resize(size.0 as usize * size.1 as usize, value.clone());
         Self { data, size, default: value }
     }
 
+    pub fn from_vec(size: Point, data: Vec<T>, default: T) -> Result<Self, SizeMismatch> {
+        assert!(0 <= size.0);
+        assert!(0 <= size.1);
+        let expected = size.0 as usize * size.1 as usize;
+        if data.len() != expected { return Err(SizeMismatch { expected, actual: data.len() }); }
+        Ok(Self { data, size, default })
+    }
+
+    // A copy grown by border tiles on each side, filled with fill, with this
+    // matrix's contents centered inside.
+    pub fn pad(&self, border: i32, fill: T) -> Matrix<T> {
+        assert!(0 <= border);
+        let Point(sx, sy) = self.size;
+        let mut result = Matrix::new(Point(sx + 2 * border, sy + 2 * border), fill);
+        result.default = self.default.clone();
+        if sx == 0 { return result; }
+        for y in 0..sy {
+            let start = self.index(Point(0, y)).unwrap();
+            let offset = result.index(Point(border, y + border)).unwrap();
+            let row = &self.data[start..start + sx as usize];
+            result.data[offset..offset + sx as usize].clone_from_slice(row);
+        }
+        result
+    }
+
+    // The inverse of pad: a copy with border tiles dropped from each side.
+    pub fn unpad(&self, border: i32) -> Matrix<T> {
+        assert!(0 <= border);
+        let size = Point(self.size.0 - 2 * border, self.size.1 - 2 * border);
+        assert!(0 <= size.0 && 0 <= size.1);
+        let mut data = Vec::with_capacity(size.0 as usize * size.1 as usize);
+        for y in 0..size.1 {
+            let Some(offset) = self.index(Point(border, y + border)) else { break; };
+            data.extend_from_slice(&self.data[offset..offset + size.0 as usize]);
+        }
+        Self { data, size, default: self.default.clone() }
+    }
+
+    // The cells in the rectangle at origin with the given size, clamped to the
+    // matrix's bounds, in row-major order.
+    pub fn iter_region(&self, origin: Point, size: Point) -> impl Iterator<Item = (Point, &T)> {
+        let (x0, x1) = (max(origin.0, 0), min(origin.0 + size.0, self.size.0));
+        let (y0, y1) = (max(origin.1, 0), min(origin.1 + size.1, self.size.1));
+        let width = max(x1 - x0, 0) as usize;
+        (y0..y1).flat_map(move |y| {
+            let start = self.index(Point(x0, y));
+            let row = start.map_or(&[][..], |x| &self.data[x..x + width]);
+            row.iter().enumerate().map(move |(i, x)| (Point(x0 + i as i32, y), x))
+        })
+    }
+
     pub fn get(&self, point: Point) -> T {
         let Some(x) = self.index(point) else { return self.default.clone(); };
         unsafe { self.data.get_unchecked(x).clone() }
@@ -96,13 +272,27 @@ impl<T: Clone> Matrix<T> {
         self.data.fill(value);
     }
 
+    pub fn fill_if<F: Fn(&T) -> bool>(&mut self, value: T, pred: F) {
+        for x in self.data.iter_mut().filter(|x| pred(x)) {
+            *x = value.clone();
+        }
+    }
+
+    // Combines two same-sized matrices cell by cell, including their defaults.
+    pub fn zip<U: Clone, V: Clone, F: Fn(&T, &U) -> V>(
+            &self, other: &Matrix<U>, f: F) -> Matrix<V> {
+        assert!(self.size == other.size, "zip: {:?} != {:?}", self.size, other.size);
+        let data = self.data.iter().zip(&other.data).map(|(a, b)| f(a, b)).collect();
+        Matrix { data, size: self.size, default: f(&self.default, &other.default) }
+    }
+
     pub fn entry_ref(&self, point: Point) -> &T {
         let Some(x) = self.index(point) else { return &self.default; };
         unsafe { self.data.get_unchecked(x) }
     }
 
     pub fn entry_mut(&mut self, point: Point) -> Option<&mut T> {
-        let Some(x) = self.index(point) else { return None; };
+        let x = self.index(point)?;
         unsafe { Some(self.data.get_unchecked_mut(x)) }
     }
 
@@ -116,6 +306,268 @@ impl<T: Clone> Matrix<T> {
     #[inline(always)]
     pub fn index(&self, point: Point) -> Option<usize> {
         if !self.contains(point) { return None; }
-        Some((point.0 + point.1 * self.size.0) as usize)
+        Some((point.0 as i64 + point.1 as i64 * self.size.0 as i64) as usize)
+    }
+
+    // Flat indices into data, for callers keeping parallel arrays.
+    pub fn point_to_index(&self, point: Point) -> Option<usize> {
+        self.index(point)
+    }
+
+    pub fn index_to_point(&self, index: usize) -> Point {
+        let width = self.size.0 as usize;
+        Point((index % width) as i32, (index / width) as i32)
+    }
+}
+
+// With the serde feature, a Matrix is serialized as its size, default, and
+// data. Deserializing checks that the data has size.0 * size.1 elements.
+
+#[cfg(feature = "serde")]
+impl<T: Clone + serde::Serialize> serde::Serialize for Matrix<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("Matrix", 3)?;
+        state.serialize_field("size", &self.size)?;
+        state.serialize_field("default", &self.default)?;
+        state.serialize_field("data", &self.data)?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Clone + serde::Deserialize<'de>> serde::Deserialize<'de> for Matrix<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename = "Matrix")]
+        struct Raw<T> { size: Point, default: T, data: Vec<T> }
+
+        let Raw { size, default, data } = Raw::deserialize(deserializer)?;
+        if size.0 < 0 || size.1 < 0 {
+            return Err(serde::de::Error::custom(format!("negative size: {:?}", size)));
+        }
+        Matrix::from_vec(size, data, default).map_err(serde::de::Error::custom)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjacency() {
+        let p = Point(3, 5);
+        assert!(p.is_adjacent8(Point(4, 5)));
+        assert!(p.is_adjacent8(Point(2, 4)));
+        assert!(!p.is_adjacent8(p));
+        assert!(!p.is_adjacent8(Point(5, 5)));
+
+        assert_eq!(p.step_toward(Point(3, 9)), Point(0, 1));
+        assert_eq!(p.step_toward(Point(-4, 1)), Point(-1, -1));
+        assert_eq!(p.step_toward(Point(7, 6)), Point(1, 1));
+        assert_eq!(p.step_toward(p), Point(0, 0));
+    }
+
+    #[test]
+    fn test_line_to() {
+        let line = Point(0, 0).line_to(Point(4, 2));
+        assert_eq!(line, vec![Point(0, 0), Point(1, 1), Point(2, 1), Point(3, 2), Point(4, 2)]);
+        let line = Point(2, 3).line_to(Point(2, 0));
+        assert_eq!(line, vec![Point(2, 3), Point(2, 2), Point(2, 1), Point(2, 0)]);
+        assert_eq!(Point(1, 1).line_to(Point(1, 1)), vec![Point(1, 1)]);
+    }
+
+    #[test]
+    fn test_cover_between() {
+        // A half-cover tile just west of the target, and a full one far off.
+        let target = Point(5, 5);
+        let cover = |p: Point| match p {
+            Point(4, 5) => 50,
+            Point(1, 5) => 100,
+            _ => 0,
+        };
+        assert_eq!(cover_between(Point(0, 5), target, cover), 50);
+        assert_eq!(cover_between(Point(1, 1), target, cover), 50);
+        assert_eq!(cover_between(Point(2, 7), target, cover), 50);
+        assert_eq!(cover_between(Point(10, 5), target, cover), 0);
+        assert_eq!(cover_between(Point(5, 0), target, cover), 0);
+        assert_eq!(cover_between(Point(4, 5), target, cover), 0);
+        assert_eq!(cover_between(target, target, cover), 0);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(Point(0, 0).normalize(10.0), Point(0, 0));
+        assert_eq!(Point(3, 4).normalize(10.0), Point(6, 8));
+        assert_eq!(Point(0, -2).normalize(5.0), Point(0, -5));
+    }
+
+    #[test]
+    fn test_from_vec() {
+        let matrix = Matrix::from_vec(Point(3, 2), vec![0, 1, 2, 3, 4, 5], -1).unwrap();
+        assert_eq!(matrix.get(Point(2, 0)), 2);
+        assert_eq!(matrix.get(Point(0, 1)), 3);
+        assert_eq!(matrix.get(Point(3, 0)), -1);
+
+        let error = Matrix::from_vec(Point(3, 2), vec![0; 5], -1).err();
+        assert_eq!(error, Some(SizeMismatch { expected: 6, actual: 5 }));
+    }
+
+    #[test]
+    fn test_large_index() {
+        // Index math must not overflow i32 for areas past i32::MAX, even
+        // though each dimension fits. index doesn't touch data, so skip it.
+        let size = Point(65536, 40000);
+        let matrix = Matrix { data: Vec::<u8>::new(), size, default: 0 };
+        assert_eq!(matrix.index(Point(65535, 32767)), Some(i32::MAX as usize));
+        assert_eq!(matrix.index(Point(0, 32768)), Some(1 << 31));
+        assert_eq!(matrix.index(Point(65535, 39999)), Some(65536 * 40000 - 1));
+        assert_eq!(matrix.index_to_point(65536 * 40000 - 1), Point(65535, 39999));
+        assert_eq!(matrix.index(Point(65536, 0)), None);
+    }
+
+    #[test]
+    fn test_fill_if() {
+        let data = vec![3, -1, 0, -7, 5, -2];
+        let mut matrix = Matrix::from_vec(Point(3, 2), data, -1).unwrap();
+        matrix.fill_if(0, |&x| x < 0);
+        assert_eq!(matrix.data, vec![3, 0, 0, 0, 5, 0]);
+        assert_eq!(matrix.default, -1);
+    }
+
+    #[test]
+    fn test_zip() {
+        let a = Matrix::from_vec(Point(3, 2), (0..6).collect(), -1).unwrap();
+        let b = Matrix::from_vec(Point(3, 2), vec![10, 20, 30, 40, 50, 60], 100).unwrap();
+        let sum = a.zip(&b, |x, y| x + y);
+        assert_eq!(sum.size, Point(3, 2));
+        assert_eq!(sum.data, vec![10, 21, 32, 43, 54, 65]);
+        assert_eq!(sum.default, 99);
+        assert_eq!(sum.get(Point(2, 1)), 65);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zip_size_mismatch() {
+        let (a, b) = (Matrix::new(Point(3, 2), 0), Matrix::new(Point(2, 3), 0));
+        a.zip(&b, |x, y| x + y);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let data = "abcdef".chars().collect();
+        let matrix = Matrix::from_vec(Point(3, 2), data, '#').unwrap();
+        let json = serde_json::to_string(&matrix).unwrap();
+        let result: Matrix<char> = serde_json::from_str(&json).unwrap();
+        assert_eq!((result.size, result.default), (matrix.size, matrix.default));
+        assert_eq!(result.data, matrix.data);
+        assert_eq!(result.get(Point(5, 5)), '#');
+
+        let json = serde_json::to_string(&Point(-3, 7)).unwrap();
+        assert_eq!(serde_json::from_str::<Point>(&json).unwrap(), Point(-3, 7));
+
+        // Mismatched sizes are rejected.
+        let json = r##"{"size":[3,2],"default":"#","data":["a","b"]}"##;
+        assert!(serde_json::from_str::<Matrix<char>>(json).is_err());
+        let json = r##"{"size":[-1,0],"default":"#","data":[]}"##;
+        assert!(serde_json::from_str::<Matrix<char>>(json).is_err());
+    }
+
+    #[test]
+    fn test_pad() {
+        let data = (0..6).collect();
+        let matrix = Matrix::from_vec(Point(3, 2), data, -1).unwrap();
+        let padded = matrix.pad(2, 9);
+        assert_eq!(padded.size, Point(7, 6));
+        assert_eq!(padded.default, -1);
+        assert_eq!(padded.get(Point(1, 1)), 9);
+        assert_eq!(padded.get(Point(2, 2)), 0);
+        assert_eq!(padded.get(Point(4, 3)), 5);
+        assert_eq!(padded.get(Point(5, 3)), 9);
+
+        let unpadded = padded.unpad(2);
+        assert_eq!(unpadded.size, matrix.size);
+        assert_eq!(unpadded.data, matrix.data);
+        assert_eq!(unpadded.default, matrix.default);
+        assert_eq!(matrix.pad(0, 9).data, matrix.data);
+    }
+
+    #[test]
+    fn test_index_round_trip() {
+        let matrix = Matrix::new(Point(3, 4), 0);
+        for i in 0..matrix.data.len() {
+            let p = matrix.index_to_point(i);
+            assert!(matrix.contains(p));
+            assert_eq!(matrix.point_to_index(p), Some(i));
+        }
+        assert_eq!(matrix.index_to_point(7), Point(1, 2));
+        assert_eq!(matrix.point_to_index(Point(3, 0)), None);
+    }
+
+    #[test]
+    fn test_radius_metric() {
+        let p = Point(3, 2);
+        assert!(RadiusMetric::Euclidean.within(Point(3, 1), 3));
+        assert!(!RadiusMetric::Euclidean.within(p, 3));
+        assert!(RadiusMetric::Chebyshev.within(p, 3));
+        assert!(!RadiusMetric::Taxicab.within(p, 4));
+        assert!(RadiusMetric::Taxicab.within(p, 5));
+        assert!(RadiusMetric::Nethack.within(p, 4));
+        assert!(!RadiusMetric::Nethack.within(p, 3));
+    }
+
+    #[test]
+    fn test_iter_region() {
+        let data = (0..20).collect();
+        let matrix = Matrix::from_vec(Point(5, 4), data, -1).unwrap();
+        let cells: Vec<_> = matrix.iter_region(Point(1, 2), Point(3, 2)).collect();
+        let mut expected = vec![];
+        for y in 2..4 {
+            for x in 1..4 { expected.push((Point(x, y), matrix.entry_ref(Point(x, y)))); }
+        }
+        assert_eq!(cells, expected);
+
+        // Rectangles are clamped to the matrix's bounds.
+        let cells: Vec<_> = matrix.iter_region(Point(-2, 3), Point(4, 5)).collect();
+        assert_eq!(cells, vec![(Point(0, 3), &15), (Point(1, 3), &16)]);
+        assert_eq!(matrix.iter_region(Point(6, 0), Point(2, 2)).count(), 0);
+    }
+
+    #[test]
+    fn test_dist2() {
+        let (a, b) = (Point(-3, 7), Point(2, -5));
+        assert_eq!(a.dist2(b), 5 * 5 + 12 * 12);
+        assert_eq!(b.dist2(a), a.dist2(b));
+        assert_eq!(a.dist2(a), 0);
+    }
+
+    #[test]
+    fn test_distance() {
+        let (p, origin) = (Point(3, 4), Point::default());
+        assert_eq!(p.distance(origin, RadiusMetric::Euclidean), 5.0);
+        assert_eq!(p.distance(origin, RadiusMetric::Chebyshev), 4.0);
+        assert_eq!(p.distance(origin, RadiusMetric::Taxicab), 7.0);
+        assert_eq!(p.distance(origin, RadiusMetric::Nethack), 5.0);
+        assert_eq!(origin.distance(p, RadiusMetric::Taxicab), 7.0);
+    }
+
+    #[test]
+    fn test_polygons() {
+        let square = [Point(0, 0), Point(4, 0), Point(4, 4), Point(0, 4)];
+        assert_eq!(polygon_area2(&square), 32);
+        assert!(polygon_contains(&square, Point(1, 2)));
+        assert!(!polygon_contains(&square, Point(5, 2)));
+        assert!(!polygon_contains(&square, Point(2, -1)));
+
+        // An L-shape, listed in the opposite orientation.
+        let ell = [Point(0, 0), Point(0, 6), Point(6, 6), Point(6, 4), Point(2, 4), Point(2, 0)];
+        assert_eq!(polygon_area2(&ell), -2 * (12 + 8));
+        assert!(polygon_contains(&ell, Point(1, 1)));
+        assert!(polygon_contains(&ell, Point(5, 5)));
+        assert!(!polygon_contains(&ell, Point(4, 2)));
+        assert!(!polygon_contains(&ell, Point(7, 5)));
     }
 }
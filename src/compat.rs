@@ -0,0 +1,18 @@
+use crate::base::{Matrix, Point};
+use crate::shadowcast::Vision;
+
+//////////////////////////////////////////////////////////////////////////////
+
+// libtcod interop
+
+impl Vision {
+    // Returns a full-map grid in world coordinates, shaped like the result of
+    // libtcod's map.is_in_fov over every tile in [0, map_size).
+    pub fn to_tcod_fov(&self, map_size: Point) -> Matrix<bool> {
+        let mut result = Matrix::new(map_size, false);
+        for &point in self.get_points_seen() {
+            result.set(point, true);
+        }
+        result
+    }
+}
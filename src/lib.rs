@@ -1,6 +1,16 @@
 mod base;
+mod compat;
+pub mod opacity;
+mod rle;
 mod shadowcast;
+mod table;
 
-pub use base::{Matrix, Point};
-pub use shadowcast::{INITIAL_VISIBILITY, VISIBILITY_LOSSES};
-pub use shadowcast::{Vision, VisionArgs};
+pub use base::{Matrix, Point, RadiusMetric, SizeMismatch};
+pub use base::{cover_between, polygon_area2, polygon_contains};
+pub use opacity::{Layered, OpacitySource, Overlay};
+pub use shadowcast::{INITIAL_VISIBILITY, SDF_UNBOUNDED, VISIBILITY_LOSSES};
+pub use shadowcast::{Falloff, TargetRule, Transform, Vision, VisionArgs, VisionResult};
+pub use shadowcast::{CastOptions, SeedTemplate, VisionBuilder, VisionError};
+pub use shadowcast::{CachedVision, VisibilityExplanation, VisibilityState};
+pub use shadowcast::{forget_explored, light_diff, max_visibility_at, union_seen};
+pub use table::{BitSet, VisibilityTable};
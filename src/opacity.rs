@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use crate::base::{Matrix, Point};
+use crate::shadowcast::{INITIAL_VISIBILITY, VISIBILITY_LOSSES};
+
+//////////////////////////////////////////////////////////////////////////////
+
+// Sources
+
+// Anything that can supply a tile's opacity to a cast: closures, opacity
+// matrices in world coordinates, and stacks of layers.
+pub trait OpacitySource {
+    fn opacity(&self, p: Point) -> i32;
+}
+
+impl<F: Fn(Point) -> i32> OpacitySource for F {
+    fn opacity(&self, p: Point) -> i32 {
+        self(p)
+    }
+}
+
+impl OpacitySource for Matrix<i32> {
+    fn opacity(&self, p: Point) -> i32 {
+        self.get(p)
+    }
+}
+
+impl OpacitySource for &Matrix<i32> {
+    fn opacity(&self, p: Point) -> i32 {
+        self.get(p)
+    }
+}
+
+// Layers such as floors and walls, where a tile is as opaque as its most
+// opaque layer.
+pub struct Layered<S: OpacitySource>(pub Vec<S>);
+
+impl<S: OpacitySource> OpacitySource for Layered<S> {
+    fn opacity(&self, p: Point) -> i32 {
+        self.0.iter().map(|x| x.opacity(p)).max().unwrap_or(0)
+    }
+}
+
+// A static opacity map with a few dynamic overrides, such as monsters, that
+// take precedence over it at their points.
+pub struct Overlay<'a> {
+    pub base: &'a Matrix<i32>,
+    pub overrides: HashMap<Point, i32>,
+}
+
+impl OpacitySource for Overlay<'_> {
+    fn opacity(&self, p: Point) -> i32 {
+        self.overrides.get(&p).copied().unwrap_or_else(|| self.base.get(p))
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+// Presets
+
+// Named opacities for common terrain, following the grass tuning described
+// alongside VISIBILITY_LOSSES. GRASS gives a radius-3 circle in a field of
+// it, and FOG a radius-4 circle.
+pub const CLEAR: i32 = 0;
+pub const WALL: i32 = INITIAL_VISIBILITY;
+pub const BRUSH: i32 = VISIBILITY_LOSSES[1];
+pub const GRASS: i32 = VISIBILITY_LOSSES[2];
+pub const FOG: i32 = VISIBILITY_LOSSES[3];
+pub const MIST: i32 = VISIBILITY_LOSSES[4];
+
+// The opacity at which a uniform field limits sight to a circle of radius r.
+// Radii outside the tuned range are clamped to it.
+pub fn opacity_for_grass_radius(r: i32) -> i32 {
+    let index = r.clamp(1, VISIBILITY_LOSSES.len() as i32) - 1;
+    VISIBILITY_LOSSES[index as usize]
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::Point;
+    use crate::shadowcast::{CastOptions, Vision, VisionArgs};
+
+    fn sight_radius(opacity: i32) -> i32 {
+        let mut vision = Vision::new(10);
+        let eye = Point(10, 10);
+        let (dir, initial_visibility, options) = (Point::default(), WALL, CastOptions::default());
+        let opacity_lookup = |_| opacity;
+        vision.compute(&VisionArgs { eye, dir, opacity_lookup, initial_visibility, options });
+        (1..=10).take_while(|&x| vision.get_visibility_at(eye + Point(x, 0)) >= 0).count() as i32
+    }
+
+    fn cast<S: OpacitySource>(opacity_lookup: S) -> Vec<(Point, i32)> {
+        let (eye, dir, options) = (Point(9, 9), Point::default(), CastOptions::default());
+        let mut vision = Vision::new(8);
+        let initial_visibility = WALL;
+        vision.compute(&VisionArgs { eye, dir, opacity_lookup, initial_visibility, options });
+        let seen = vision.points_seen_sorted();
+        seen.iter().map(|&p| (p, vision.get_visibility_at(p))).collect()
+    }
+
+    #[test]
+    fn test_sources_agree() {
+        let mut walls = Matrix::new(Point(20, 20), WALL);
+        let mut grass = Matrix::new(Point(20, 20), CLEAR);
+        for y in 1..19 {
+            for x in 1..19 {
+                let p = Point(x, y);
+                walls.set(p, if (x * 7 + y * 3) % 11 == 0 { WALL } else { CLEAR });
+                grass.set(p, if (x + y) % 5 == 0 { GRASS } else { CLEAR });
+            }
+        }
+        let combined = |p: Point| std::cmp::max(walls.get(p), grass.get(p));
+        let data = (0..walls.data.len()).map(|i| combined(walls.index_to_point(i))).collect();
+        let matrix = Matrix::from_vec(walls.size, data, WALL).unwrap();
+
+        let expected = cast(combined);
+        assert!(expected.iter().any(|&(_, v)| 0 < v && v < WALL));
+        assert_eq!(cast(&matrix), expected);
+        assert_eq!(cast(Layered(vec![&walls, &grass])), expected);
+        assert_eq!(cast(matrix), expected);
+    }
+
+    #[test]
+    fn test_overlay() {
+        // A corridor along y = 1, with walls everywhere else.
+        let mut base = Matrix::new(Point(10, 3), WALL);
+        for x in 0..10 { base.set(Point(x, 1), CLEAR); }
+
+        let mut vision = Vision::new(12);
+        let eye = Point(0, 1);
+        let overrides = HashMap::new();
+        vision.compute(&VisionArgs::new(eye, Overlay { base: &base, overrides }));
+        assert_eq!(vision.get_visibility_at(Point(9, 1)), INITIAL_VISIBILITY);
+
+        // A monster in the corridor blocks sight to the tiles behind it.
+        let overrides = HashMap::from([(Point(4, 1), WALL), (Point(20, 20), CLEAR)]);
+        vision.compute(&VisionArgs::new(eye, Overlay { base: &base, overrides }));
+        assert_eq!(vision.get_visibility_at(Point(3, 1)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(4, 1)), 0);
+        assert_eq!(vision.get_visibility_at(Point(5, 1)), -1);
+        assert_eq!(vision.get_visibility_at(Point(9, 1)), -1);
+    }
+
+    #[test]
+    fn test_grass_radius() {
+        assert_eq!(sight_radius(GRASS), 3);
+        assert_eq!(sight_radius(FOG), 4);
+        for r in 1..=7 {
+            assert_eq!(sight_radius(opacity_for_grass_radius(r)), r);
+        }
+        assert_eq!(opacity_for_grass_radius(0), WALL);
+        assert_eq!(opacity_for_grass_radius(20), VISIBILITY_LOSSES[6]);
+    }
+}
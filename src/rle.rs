@@ -0,0 +1,138 @@
+use crate::base::Point;
+use crate::shadowcast::Vision;
+
+//////////////////////////////////////////////////////////////////////////////
+
+// Run-length encoding
+
+// The encoding is a sequence of LEB128 varints: the bounding box's min corner
+// (zigzag-encoded) and size, then for each row of the box, alternating runs
+// of unseen and seen tiles, starting with unseen, which sum to the width.
+// An empty seen set encodes to no bytes at all.
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<u32> {
+    let mut result = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = bytes.next()?;
+        result |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 { return Some(result); }
+    }
+    None
+}
+
+// Reads a varint that must fit in an i32, as sizes and runs do.
+fn read_len(bytes: &mut impl Iterator<Item = u8>) -> Option<i32> {
+    i32::try_from(read_varint(bytes)?).ok()
+}
+
+fn zigzag(x: i32) -> u32 {
+    ((x << 1) ^ (x >> 31)) as u32
+}
+
+fn unzigzag(x: u32) -> i32 {
+    ((x >> 1) as i32) ^ -((x & 1) as i32)
+}
+
+impl Vision {
+    // A compact encoding of the last cast's seen tiles, for network sync.
+    pub fn encode_rle(&self) -> Vec<u8> {
+        let mut result = vec![];
+        let Some((min, max)) = self.bounding_box() else { return result; };
+        let size = max - min + Point(1, 1);
+        for x in [zigzag(min.0), zigzag(min.1), size.0 as u32, size.1 as u32] {
+            write_varint(&mut result, x);
+        }
+        for y in min.1..=max.1 {
+            let (mut seen, mut run) = (false, 0);
+            for x in min.0..=max.0 {
+                if (self.get_visibility_at(Point(x, y)) >= 0) != seen {
+                    write_varint(&mut result, run);
+                    (seen, run) = (!seen, 0);
+                }
+                run += 1;
+            }
+            write_varint(&mut result, run);
+        }
+        result
+    }
+
+    // Decodes the output of encode_rle into seen tiles, sorted by (y, x).
+    // Malformed input, including sizes that overflow or runs that overshoot
+    // their row, yields the tiles decoded before the error.
+    pub fn decode_rle(bytes: &[u8]) -> Vec<Point> {
+        let mut result = vec![];
+        let mut bytes = bytes.iter().copied();
+        let mut decode = || -> Option<()> {
+            let min = Point(unzigzag(read_varint(&mut bytes)?), unzigzag(read_varint(&mut bytes)?));
+            let (width, height) = (read_len(&mut bytes)?, read_len(&mut bytes)?);
+            let max = Point(min.0.checked_add(width)?, min.1.checked_add(height)?);
+            for y in min.1..max.1 {
+                let (mut seen, mut x) = (false, min.0);
+                while x < max.0 {
+                    let run = read_len(&mut bytes)?;
+                    if run > max.0 - x { return None; }
+                    if seen { result.extend((x..x + run).map(|x| Point(x, y))); }
+                    (seen, x) = (!seen, x + run);
+                }
+            }
+            Some(())
+        };
+        decode();
+        result
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shadowcast::{CastOptions, VisionArgs, INITIAL_VISIBILITY};
+
+    fn cast(eye: Point, opacity_lookup: impl Fn(Point) -> i32) -> Vision {
+        let mut vision = Vision::new(10);
+        let (dir, options) = (Point::default(), CastOptions::default());
+        let initial_visibility = INITIAL_VISIBILITY;
+        vision.compute(&VisionArgs { eye, dir, opacity_lookup, initial_visibility, options });
+        vision
+    }
+
+    #[test]
+    fn test_rle_round_trip() {
+        let vision = cast(Point(-40, 7), |_| 0);
+        let bytes = vision.encode_rle();
+        assert_eq!(Vision::decode_rle(&bytes), vision.points_seen_sorted());
+
+        // A flat list needs at least two bytes per tile.
+        let points = vision.get_points_seen().len();
+        assert!(points > 300);
+        assert!(bytes.len() * 4 < points);
+
+        // Holes in the middle of a row, here a pillar's shadow, round-trip too.
+        let vision = cast(Point(3, 3), |p| if p == Point(3, 6) { INITIAL_VISIBILITY } else { 0 });
+        assert_eq!(vision.get_visibility_at(Point(3, 10)), -1);
+        assert_eq!(Vision::decode_rle(&vision.encode_rle()), vision.points_seen_sorted());
+        assert!(Vision::decode_rle(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_rle_malformed() {
+        // A truncated encoding yields the rows decoded so far.
+        assert_eq!(Vision::decode_rle(&[0, 0, 2, 2, 0, 2]), vec![Point(0, 0), Point(1, 0)]);
+
+        // Sizes past i32::MAX, sizes that overflow the min corner, and runs
+        // past the end of their row are all rejected.
+        assert!(Vision::decode_rle(&[0, 0, 0xff, 0xff, 0xff, 0xff, 0x0f, 1]).is_empty());
+        assert!(Vision::decode_rle(&[2, 0, 0xff, 0xff, 0xff, 0xff, 0x07, 1]).is_empty());
+        assert!(Vision::decode_rle(&[0, 0, 2, 1, 0, 0xff, 0xff, 0xff, 0xff, 0x07]).is_empty());
+        assert_eq!(Vision::decode_rle(&[0, 0, 2, 2, 0, 2, 1, 5]), vec![Point(0, 0), Point(1, 0)]);
+    }
+}
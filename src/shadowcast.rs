@@ -1,7 +1,9 @@
 use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::ops::Mul;
 
-use crate::base::{Matrix, Point};
+use crate::base::{Matrix, Point, RadiusMetric};
+use crate::opacity::OpacitySource;
 
 //////////////////////////////////////////////////////////////////////////////
 
@@ -35,8 +37,15 @@ fn div_ceil(lhs: i32, rhs: i32) -> i32 {
 pub const INITIAL_VISIBILITY: i32 = 100;
 pub const VISIBILITY_LOSSES: [i32; 7] = [100, 75, 45, 30, 24, 19, 15];
 
+// The magnitude of Vision::to_sdf's distance for a tile whose bounds hold no
+// tile of the other kind: -SDF_UNBOUNDED if all are seen, else SDF_UNBOUNDED.
+pub const SDF_UNBOUNDED: i32 = i32::MAX;
+
+// The distance chamfer_distance leaves in cells with no true cell in reach.
+const CHAMFER_FAR: i32 = i32::MAX / 4;
+
 #[derive(Clone, Copy, Debug)]
-struct Transform([[i32; 2]; 2]);
+pub struct Transform([[i32; 2]; 2]);
 
 const TRANSFORMS: [Transform; 4] = [
     Transform([[ 1,  0], [ 0,  1]]),
@@ -56,6 +65,19 @@ impl Mul<Point> for Transform {
     }
 }
 
+// Quarter turns, where ROT_90 takes +x to +y. These match TRANSFORMS.
+impl Transform {
+    pub const IDENTITY: Transform = TRANSFORMS[0];
+    pub const ROT_90: Transform = TRANSFORMS[1];
+    pub const ROT_180: Transform = TRANSFORMS[2];
+    pub const ROT_270: Transform = TRANSFORMS[3];
+
+    // The method form of self * p.
+    pub fn apply(&self, p: Point) -> Point {
+        *self * p
+    }
+}
+
 //////////////////////////////////////////////////////////////////////////////
 
 // Rational slopes
@@ -111,40 +133,546 @@ struct SlopeRanges {
     items: Vec<SlopeRange>,
 }
 
+fn lookup_span<F: OpacitySource>(opacity_lookup: &F) -> impl FnMut(Point, Point, &mut [i32]) + '_ {
+    move |mut point, step, out| {
+        for x in out {
+            *x = opacity_lookup.opacity(point);
+            point = point + step;
+        }
+    }
+}
+
+fn in_region(region: (Point, Point), p: Point) -> bool {
+    let (min, max) = region;
+    min.0 <= p.0 && p.0 <= max.0 && min.1 <= p.1 && p.1 <= max.1
+}
+
+// A tile at offset (x, y) from the eye is within radius iff x^2 + y^2 <= r2,
+// for this r2. Computed in i64, since it overflows i32 for large radii.
+fn disc_r2(radius: i32) -> i64 {
+    let radius = radius as i64;
+    radius * radius + radius
+}
+
+// The largest |width| of a tile within the disc at this depth, or -1 if none.
+fn disc_reach(r2: i64, depth: i32) -> i32 {
+    let d2 = depth as i64 * depth as i64;
+    if d2 > r2 { -1 } else { (r2 - d2).isqrt() as i32 }
+}
+
+// Like disc_reach, but for any metric. For Euclidean, r2 is the bound to use.
+fn metric_reach(metric: RadiusMetric, radius: i32, r2: i64, depth: i32) -> i32 {
+    if metric == RadiusMetric::Euclidean { return disc_reach(r2, depth); }
+    if depth > radius || !metric.within(Point(depth, 0), radius) { return -1; }
+
+    // Binary search for the last width in [0, depth] in range.
+    let (mut lo, mut hi) = (0, depth);
+    while lo < hi {
+        let mid = lo + (hi - lo + 1) / 2;
+        if metric.within(Point(depth, mid), radius) { lo = mid; } else { hi = mid - 1; }
+    }
+    lo
+}
+
+// Whether p is within radius of eye under metric. The nudge widens or
+// narrows the Euclidean disc, as for CastOptions::boundary_epsilon.
+fn within_radius(metric: RadiusMetric, eye: Point, p: Point, radius: i32, nudge: i64) -> bool {
+    if metric == RadiusMetric::Euclidean { return eye.dist2(p) <= disc_r2(radius) + nudge; }
+    let delta = p - eye;
+    delta.len_l1() <= radius && metric.within(delta, radius)
+}
+
+// The dir from eye toward target, as used by VisionArgs::facing_toward.
+fn heading(eye: Point, target: Point) -> Point {
+    let (x, y) = (target.0 as i64 - eye.0 as i64, target.1 as i64 - eye.1 as i64);
+    let scale = std::cmp::max(x.abs(), y.abs());
+    let shrink = |z: i64| {
+        if scale <= 64 { z as i32 } else { (z as f64 * 64.0 / scale as f64).round() as i32 }
+    };
+    let (x, y) = (shrink(x), shrink(y));
+    let (mut a, mut b) = (x.abs(), y.abs());
+    while b != 0 { (a, b) = (b, a % b); }
+    if a == 0 { Point::default() } else { Point(x / a, y / a) }
+}
+
+// Rotations by half_angle radians to the left and right, clamped to [0, pi / 2]
+// and approximated with integer entries, accurate to ~1 degree.
+fn cone_rotations(half_angle: f64) -> (Transform, Transform) {
+    let half_angle = half_angle.clamp(0.0, std::f64::consts::FRAC_PI_2);
+    let scale = |x: f64| (64.0 * x).round() as i32;
+    let (c, s) = (scale(half_angle.cos()), scale(half_angle.sin()));
+    (Transform([[c, s], [-s, c]]), Transform([[c, -s], [s, c]]))
+}
+
+// Whether any point within radius of center lies in the wedge between the
+// slopes min and max, in the frame where the wedge opens along +x.
+fn wedge_near_disc(min: Slope, max: Slope, center: Point, radius: i32) -> bool {
+    let Point(x, y) = center;
+    if x > 0 && min.num as i64 * x as i64 <= y as i64 * min.den as i64 &&
+                y as i64 * max.den as i64 <= max.num as i64 * x as i64 {
+        return true;
+    }
+    let (x, y, radius) = (x as f64, y as f64, radius as f64);
+    [min, max].iter().any(|slope| {
+        let (dx, dy) = (slope.den as f64, slope.num as f64);
+        let length = (dx * dx + dy * dy).sqrt();
+        let along = (x * dx + y * dy) / length;
+        let cross = (x * dy - y * dx).abs() / length;
+        let distance = if along < 0.0 { (x * x + y * y).sqrt() } else { cross };
+        distance <= radius
+    })
+}
+
+// A chamfer distance transform: the approximate distance from each cell to
+// the nearest true cell of mask, in thirds of a tile, with diagonal steps
+// costing 4. Cells with no true cell in the mask keep CHAMFER_FAR.
+fn chamfer_distance(mask: &Matrix<bool>) -> Matrix<i32> {
+    let mut result = Matrix::new(mask.size, CHAMFER_FAR);
+    for (x, &on) in result.data.iter_mut().zip(&mask.data) {
+        if on { *x = 0; }
+    }
+    let Point(w, h) = mask.size;
+    let forward = [(Point(-1, 0), 3), (Point(-1, -1), 4), (Point(0, -1), 3), (Point(1, -1), 4)];
+    let passes = [(forward, false), (forward.map(|(p, d)| (Point(-p.0, -p.1), d)), true)];
+    for (steps, reverse) in passes {
+        for i in 0..(w * h) {
+            let i = if reverse { w * h - 1 - i } else { i };
+            let p = Point(i % w, i / w);
+            let best = steps.iter().filter(|(step, _)| result.contains(p + *step))
+                .map(|&(step, cost)| result.get(p + step) + cost).min();
+            let entry = result.entry_mut(p).unwrap();
+            if let Some(x) = best { *entry = std::cmp::min(*entry, x); }
+        }
+    }
+    result
+}
+
+// Restricts the span of tiles base + w * step, for w in [lo, hi], to those in
+// the inclusive rectangle region. The step must be a unit vector along an axis.
+fn clip_span(region: (Point, Point), base: Point, step: Point, lo: i32, hi: i32) -> (i32, i32) {
+    let (min, max) = region;
+    let axes = [(base.0, step.0, min.0, max.0), (base.1, step.1, min.1, max.1)];
+    axes.iter().fold((lo, hi), |(lo, hi), &(base, step, min, max)| {
+        if step == 0 {
+            return if min <= base && base <= max { (lo, hi) } else { (lo, lo - 1) };
+        }
+        let (a, b) = ((min - base) * step, (max - base) * step);
+        (std::cmp::max(lo, std::cmp::min(a, b)), std::cmp::min(hi, std::cmp::max(a, b)))
+    })
+}
+
+// Visits each tile whose interior the segment from -> to passes through, in
+// order, until visit returns false. Returns true if the whole segment was
+// visited. Where the segment crosses a tile corner, both side tiles are skipped.
+fn trace_ray(from: (f64, f64), to: (f64, f64), mut visit: impl FnMut(Point) -> bool) -> bool {
+    let sign = |x: f64| if x > 0.0 { 1 } else if x < 0.0 { -1 } else { 0 };
+    let (x0, y0) = (from.0 + 0.5, from.1 + 0.5);
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let mut tile = Point(x0.floor() as i32, y0.floor() as i32);
+    let last = Point((to.0 + 0.5).floor() as i32, (to.1 + 0.5).floor() as i32);
+    let step = Point(sign(dx), sign(dy));
+
+    let t_delta = |d: f64| if d == 0.0 { f64::INFINITY } else { 1.0 / d.abs() };
+    let t_first = |p: f64, d: f64| match sign(d) {
+        1 => (p.floor() + 1.0 - p) / d,
+        -1 => (p - p.floor()) / -d,
+        _ => f64::INFINITY,
+    };
+    let (delta_x, delta_y) = (t_delta(dx), t_delta(dy));
+    let (mut t_x, mut t_y) = (t_first(x0, dx), t_first(y0, dy));
+
+    loop {
+        if !visit(tile) { return false; }
+        if tile == last || (t_x > 1.0 && t_y > 1.0) { return true; }
+        let (step_x, step_y) = (t_x <= t_y, t_y <= t_x);
+        if step_x {
+            tile.0 += step.0;
+            t_x += delta_x;
+        }
+        if step_y {
+            tile.1 += step.1;
+            t_y += delta_y;
+        }
+    }
+}
+
+// A slow, independent check of which tiles an omnidirectional cast sees, for
+// binary opacity. Within a quadrant, the tile at (depth, width) spans slopes
+// ((2 * width - 1) / (2 * depth), (2 * width + 1) / (2 * depth)) and is seen if
+// some slope in that open interval isn't covered by the closed interval of an
+// opaque or out-of-range tile at a smaller depth. Slopes here have small
+// denominators, so equal rationals compare equal as f64.
+#[cfg(debug_assertions)]
+fn brute_force_visible(opaque: impl Fn(Point) -> bool, radius: i32, target: Point) -> bool {
+    let r2 = disc_r2(radius);
+    if target == Point::default() { return true; }
+    if target.len_l2_squared() > r2 { return false; }
+
+    TRANSFORMS.iter().any(|&transform| {
+        let Transform([[a00, a01], [a10, a11]]) = transform;
+        let Point(x, y) = Transform([[a00, -a01], [-a10, a11]]) * target;
+        if x <= 0 || x < y.abs() { return false; }
+
+        let slopes = |d: i32, w: i32| ((2 * w - 1) as f64 / (2 * d) as f64,
+                                      (2 * w + 1) as f64 / (2 * d) as f64);
+        let mut blockers = vec![];
+        for d in 1..x {
+            for w in -d..=d {
+                if Point(d, w).len_l2_squared() > r2 || opaque(transform * Point(d, w)) {
+                    blockers.push(slopes(d, w));
+                }
+            }
+        }
+        blockers.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let (lo, hi) = slopes(x, y);
+        let (mut cur, hi) = (lo.max(-1.0), hi.min(1.0));
+        for (l, r) in blockers {
+            if l > cur { break; }
+            cur = cur.max(r);
+        }
+        cur < hi
+    })
+}
+
 //////////////////////////////////////////////////////////////////////////////
 
 // Public API
 
-pub struct VisionArgs<F: Fn(Point) -> i32> {
-    eye: Point,
-    dir: Point, // we limit to 120 degree directional FOV if dir != (0, 0)
-    opacity_lookup: F,
-    initial_visibility: i32,
+// How a partially-transparent tile attenuates a beam passing through it.
+// In both cases, the loss is scaled up for beams crossing the tile obliquely.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Falloff {
+    // Subtract the tile's opacity from the beam's visibility.
+    #[default]
+    Subtractive,
+    // Multiply the beam's visibility by (1 - opacity / scale), so dim beams
+    // lose less in absolute terms and fade out smoothly. As with Subtractive,
+    // a tile with opacity at least the beam's visibility blocks it outright,
+    // so walls block at any scale. Casts panic unless scale is positive.
+    Multiplicative(f32),
+}
+
+// How much of a target tile must be visible for can_see to return true.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TargetRule {
+    // Any part of the tile, matching how compute marks tiles as seen.
+    #[default]
+    AnyCorner,
+    // The ray to the tile's center must not pass through any opaque tile.
+    Center,
+    // Every ray to the tile must reach it.
+    AllCorners,
+}
+
+impl Falloff {
+    fn validate(&self) {
+        if let Falloff::Multiplicative(scale) = *self {
+            assert!(scale > 0.0, "Multiplicative falloff needs a positive scale: {}", scale);
+        }
+    }
+
+    fn attenuate(&self, visibility: i32, opacity: i32, x: i32, y: i32) -> i32 {
+        if opacity >= visibility { return 0; }
+        let r = 1.0 + (0.5 * y.abs() as f64) / (x as f64);
+        match *self {
+            Falloff::Subtractive => {
+                std::cmp::max(visibility - (r * opacity as f64) as i32, 0)
+            }
+            Falloff::Multiplicative(scale) => {
+                let factor = 1.0 - r * opacity as f64 / scale as f64;
+                if factor <= 0.0 { return 0; }
+                (visibility as f64 * factor) as i32
+            }
+        }
+    }
+}
+
+// Optional cast behaviors. Every default leaves the cast unchanged.
+#[derive(Clone, Debug, Default)]
+pub struct CastOptions {
+    // If positive, each opaque tile seen also reveals the tile just beyond it
+    // along the ray with this visibility, hinting at the wall's thickness.
+    pub wall_bleed: i32,
+    pub falloff: Falloff,
+    // If set, the inclusive world-space (min, max) rectangle outside which
+    // tiles are neither queried for opacity nor marked seen.
+    pub valid_region: Option<(Point, Point)>,
+    // World-space tiles, such as allies, that never block sight regardless of
+    // their opacity. Scanned linearly for each span, so keep this short.
+    pub transparent_override: Vec<Point>,
+    // Tiles with opacity at most this height are seen over rather than through,
+    // as from a balcony, so they don't block or attenuate sight at all.
+    pub see_over_height: i32,
+    // If larger than the Vision's radius, compute also finds the tiles within
+    // this radius that have line of sight, to mark explored without being
+    // currently visible. See get_points_revealed.
+    pub reveal_radius: Option<i32>,
+    // If positive, beams narrower than this many hundredths of a tile at the
+    // edge of the cast are dropped. This bounds the number of beams on finely
+    // perforated maps, at the cost of missing tiles seen only through them.
+    pub min_range_width: i32,
+    // A disc, given by its center and radius, where min_range_width doesn't
+    // apply: beams that could reach it are kept at full precision, so the
+    // cast only approximates the rest of the field, e.g. away from a cursor.
+    pub focus: Option<(Point, i32)>,
+    // Added to the world position of each tile seen, without changing the
+    // cast's geometry, e.g. to project a staircase's view onto the level below.
+    // The Vision is then centered on eye + projection_offset.
+    pub projection_offset: Point,
+    // If set, a tile whose opacity exactly equals the beam's visibility leaves
+    // a residual visibility of 1, so the tiles beyond it are faintly seen.
+    pub residual_on_equal: bool,
+    // If set along with a dir, replaces the 120-degree cone with everything
+    // not strictly behind the eye: tiles with (tile - eye).dot(dir) >= 0.
+    pub exclude_behind: bool,
+    // If positive, light bends slightly around the edges of walls: each beam
+    // that runs into a wall's edge continues with this visibility through the
+    // half of the edge tile nearest it, dimly lighting tiles just around the
+    // corner. Beams no brighter than this don't diffract further.
+    pub diffraction: i32,
+    // The part of its target that can_see requires to be visible.
+    pub target_rule: TargetRule,
+    // If set, partial opacity doesn't attenuate the eye's 8 neighbors, as if
+    // the viewer parts the grass around them. Walls there still block.
+    pub clear_first_ring: bool,
+    // Added to the r^2 + r bound of the disc test, to nudge which tiles on
+    // the disc's edge are included without changing the radius. Values above
+    // the radius act like the radius, so the disc stays within the Vision.
+    pub boundary_epsilon: i32,
+    // Adjacent beams whose visibilities differ by at most this much merge,
+    // taking the lower visibility, to limit fragmentation in uneven grass.
+    pub merge_tolerance: i32,
+}
+
+impl CastOptions {
+    fn boundary_r2_nudge(&self, radius: i32) -> i64 {
+        std::cmp::min(self.boundary_epsilon, radius) as i64
+    }
+}
+
+pub struct VisionArgs<F: OpacitySource> {
+    pub(crate) eye: Point,
+    pub(crate) dir: Point, // we limit to 120 degree directional FOV if dir != (0, 0)
+    pub(crate) opacity_lookup: F,
+    pub(crate) initial_visibility: i32,
+    pub(crate) options: CastOptions,
+}
+
+impl<F: OpacitySource> VisionArgs<F> {
+    /// Omnidirectional args with INITIAL_VISIBILITY and default options.
+    ///
+    /// ```
+    /// use shadowcast::{INITIAL_VISIBILITY, Point, Vision, VisionArgs};
+    ///
+    /// let wall = |p: Point| if p == Point(2, 0) { INITIAL_VISIBILITY } else { 0 };
+    /// let args = VisionArgs::new(Point(0, 0), wall).with_dir(Point(1, 0));
+    /// let mut vision = Vision::new(4);
+    /// vision.compute(&args);
+    /// assert_eq!(vision.get_visibility_at(Point(1, 0)), INITIAL_VISIBILITY);
+    /// assert_eq!(vision.get_visibility_at(Point(2, 0)), 0);
+    /// assert_eq!(vision.get_visibility_at(Point(3, 0)), -1);
+    /// ```
+    pub fn new(eye: Point, opacity_lookup: F) -> Self {
+        let (dir, initial_visibility) = (Point::default(), INITIAL_VISIBILITY);
+        Self { eye, dir, opacity_lookup, initial_visibility, options: CastOptions::default() }
+    }
+
+    pub fn with_dir(mut self, dir: Point) -> Self {
+        self.dir = dir;
+        self
+    }
+
+    pub fn with_initial_visibility(mut self, initial_visibility: i32) -> Self {
+        self.initial_visibility = initial_visibility;
+        self
+    }
+
+    /// Replaces the cast's options, which all default to an unchanged cast.
+    ///
+    /// ```
+    /// use shadowcast::{CastOptions, INITIAL_VISIBILITY, Point, Vision, VisionArgs};
+    ///
+    /// let wall = |p: Point| if p.0 == 2 || p.0 == 3 { INITIAL_VISIBILITY } else { 0 };
+    /// let mut options = CastOptions::default();
+    /// options.wall_bleed = 5;
+    /// let args = VisionArgs::new(Point(0, 0), wall).with_options(options);
+    /// let mut vision = Vision::new(6);
+    /// vision.compute(&args);
+    /// assert_eq!(vision.get_visibility_at(Point(2, 0)), 0);
+    /// assert_eq!(vision.get_visibility_at(Point(3, 0)), 5);
+    /// ```
+    pub fn with_options(mut self, options: CastOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Sets eye and points dir at target, or clears dir if they're equal. Only
+    // dir's heading matters, so it's shrunk to at most 64 in each coordinate,
+    // in lowest terms, which keeps the seeding math far from overflow.
+    pub fn facing_toward(mut self, eye: Point, target: Point) -> Self {
+        (self.eye, self.dir) = (eye, heading(eye, target));
+        self
+    }
+}
+
+// Tri-state fog of war, as maintained by Vision::compute_fog.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum VisibilityState {
+    #[default]
+    Unknown,
+    Remembered,
+    Visible,
 }
 
 pub struct Vision {
     radius: i32,
+    metric: RadiusMetric,
+    // The default for casts' initial visibility. See Vision::args.
+    initial_visibility: i32,
+    // The last cast's boundary_epsilon, as a nudge to the disc's r^2 + r.
+    nudge: i64,
     offset: Point,
     points_seen: Vec<Point>,
     visibility: Matrix<i32>,
 
+    // Per-tile tags, valid only after compute_tagged. Allocated on first use.
+    tags: Matrix<u8>,
+    tagged: bool,
+
+    // A second cast over the reveal radius, valid only after a compute with
+    // reveal_radius set. Allocated on first use.
+    revealer: Option<Box<Vision>>,
+    revealed: bool,
+
     // Allocations used in compute
     prev: SlopeRanges,
     next: SlopeRanges,
+    row: Vec<i32>,
 }
 
-impl Vision {
-    pub fn new(radius: i32) -> Self {
+// Configures a Vision: its radius, the metric that bounds its field, and the
+// default initial visibility for the args it makes with Vision::args. build
+// checks these rather than panicking. With any metric, the field is also
+// clipped to the square within radius.
+#[derive(Clone, Copy, Debug)]
+pub struct VisionBuilder {
+    radius: i32,
+    metric: RadiusMetric,
+    initial_visibility: i32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VisionError {
+    NegativeRadius(i32),
+    NegativeInitialVisibility(i32),
+}
+
+impl std::fmt::Display for VisionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VisionError::NegativeRadius(x) => write!(f, "negative radius: {}", x),
+            VisionError::NegativeInitialVisibility(x) => {
+                write!(f, "negative initial visibility: {}", x)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VisionError {}
+
+impl Default for VisionBuilder {
+    fn default() -> Self {
+        Self { radius: 0, metric: RadiusMetric::default(), initial_visibility: INITIAL_VISIBILITY }
+    }
+}
+
+impl VisionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn radius(mut self, radius: i32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn metric(mut self, metric: RadiusMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn initial_visibility(mut self, initial_visibility: i32) -> Self {
+        self.initial_visibility = initial_visibility;
+        self
+    }
+
+    pub fn build(self) -> Result<Vision, VisionError> {
+        let Self { radius, metric, initial_visibility } = self;
+        if radius < 0 { return Err(VisionError::NegativeRadius(radius)); }
+        if initial_visibility < 0 {
+            return Err(VisionError::NegativeInitialVisibility(initial_visibility));
+        }
         let side = 2 * radius + 1;
-        let size = Point(side, side);
-        Self {
+        Ok(Vision {
             radius,
+            metric,
+            initial_visibility,
+            nudge: 0,
             offset: Point::default(),
             points_seen: vec![],
-            visibility: Matrix::new(size, -1),
+            visibility: Matrix::new(Point(side, side), -1),
+            tags: Matrix::default(),
+            tagged: false,
+            revealer: None,
+            revealed: false,
             prev: SlopeRanges::default(),
             next: SlopeRanges::default(),
-        }
+            row: vec![],
+        })
+    }
+}
+
+// The initial beams for a cast facing a fixed dir, as computed by
+// Vision::precompute_seed, so many casts with that facing can share them.
+#[derive(Clone, Debug)]
+pub struct SeedTemplate {
+    dir: Point,
+    ranges: Vec<SlopeRange>,
+}
+
+impl SeedTemplate {
+    pub fn dir(&self) -> Point {
+        self.dir
+    }
+}
+
+// Why a target is or isn't visible, as reported by Vision::explain. The
+// quadrant is the index in TRANSFORMS of the one that scans toward it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VisibilityExplanation {
+    pub visible: bool,
+    pub visibility: i32,
+    pub blockers: Vec<Point>,
+    pub quadrant: usize,
+}
+
+// A cast's results, moved out of the Vision that computed them by
+// compute_result, so that the Vision can be reused for other casts.
+pub struct VisionResult {
+    radius: i32,
+    offset: Point,
+    points_seen: Vec<Point>,
+    visibility: Matrix<i32>,
+}
+
+impl VisionResult {
+    pub fn radius(&self) -> i32 {
+        self.radius
+    }
+
+    pub fn offset(&self) -> Point {
+        self.offset
     }
 
     pub fn get_points_seen(&self) -> &[Point] {
@@ -154,441 +682,1563 @@ impl Vision {
     pub fn get_visibility_at(&self, p: Point) -> i32 {
         self.visibility.get(p + self.offset)
     }
+}
 
-    pub fn clear(&mut self, pos: Point, visibility: i32) {
-        // Sparse clear optimization. The dense clear has much better constant
-        // factors so we only switch over when it's sufficiently sparse.
-        if self.visibility.data.len() < 16 * self.points_seen.len() {
-            self.visibility.fill(-1);
-        } else {
-            for &point in &self.points_seen {
-                debug_assert!(self.visibility.get(point + self.offset) >= 0);
-                self.visibility.set(point + self.offset, -1);
-            }
-            debug_assert!(self.visibility.data.iter().all(|&x| x == -1));
-        }
+impl Vision {
+    // Panics if radius is negative. See VisionBuilder for other settings.
+    pub fn new(radius: i32) -> Self {
+        VisionBuilder::new().radius(radius).build().unwrap()
+    }
 
-        let center = Point(self.radius, self.radius);
-        self.offset = center - pos;
-        self.points_seen.clear();
+    // Like VisionArgs::new, but with this Vision's default initial visibility.
+    pub fn args<F: OpacitySource>(&self, eye: Point, opacity_lookup: F) -> VisionArgs<F> {
+        VisionArgs::new(eye, opacity_lookup).with_initial_visibility(self.initial_visibility)
+    }
 
-        self.visibility.set(center, visibility);
-        self.points_seen.push(pos);
+    // Changes the radius, keeping the field centered on the same eye. Reuses
+    // the visibility buffer's allocation unless it has to grow past it, and
+    // discards the last cast's results.
+    pub fn resize(&mut self, radius: i32) {
+        assert!(0 <= radius);
+        let eye = self.eye();
+        let side = 2 * radius + 1;
+        self.visibility.data.clear();
+        self.visibility.data.resize(side as usize * side as usize, -1);
+        self.visibility.size = Point(side, side);
 
-        self.prev.depth = 1;
-        self.next.depth = 2;
+        self.radius = radius;
+        self.offset = Point(radius, radius) - eye;
+        self.points_seen.clear();
+        (self.tagged, self.revealed) = (false, false);
         self.prev.items.clear();
         self.next.items.clear();
     }
 
-    pub fn can_see<F: Fn(Point) -> i32>(&mut self, args: &VisionArgs<F>, target: Point) -> bool {
-        if args.eye == target { return true; }
-
-        let radius = self.radius;
-        let r2 = radius * radius + radius;
-        let Point(x, y) = target - args.eye;
-        if x * x + y * y > r2 { return false; }
+    // Builds a Vision around caller-allocated buffers. Their contents are
+    // discarded, but their allocations are reused.
+    pub fn from_parts(
+            radius: i32, mut visibility: Matrix<i32>, mut points_seen: Vec<Point>) -> Self {
+        let side = 2 * radius + 1;
+        assert!(visibility.size == Point(side, side));
+        visibility.default = -1;
+        visibility.fill(-1);
+        points_seen.clear();
+        Self {
+            radius,
+            metric: RadiusMetric::default(),
+            initial_visibility: INITIAL_VISIBILITY,
+            nudge: 0,
+            offset: Point::default(),
+            points_seen,
+            visibility,
+            tags: Matrix::default(),
+            tagged: false,
+            revealer: None,
+            revealed: false,
+            prev: SlopeRanges::default(),
+            next: SlopeRanges::default(),
+            row: vec![],
+        }
+    }
 
-        let limit = std::cmp::max(x.abs(), y.abs());
+    // A copy of the seen tiles sorted by (y, x), independent of cast order.
+    pub fn points_seen_sorted(&self) -> Vec<Point> {
+        let mut result = self.points_seen.clone();
+        result.sort_unstable_by_key(|p| (p.1, p.0));
+        result
+    }
 
-        self.clear(args.eye, args.initial_visibility);
-        self.seed_ranges(args.dir, Some(target - args.eye));
-        self.execute(args.eye, limit, &args.opacity_lookup);
+    // Inclusive world-space (min, max) corners of the tiles seen by the last
+    // cast, or None if nothing has been cast.
+    pub fn bounding_box(&self) -> Option<(Point, Point)> {
+        let (&first, rest) = self.points_seen.split_first()?;
+        Some(rest.iter().fold((first, first), |(min, max), &p| {
+            let min = Point(std::cmp::min(min.0, p.0), std::cmp::min(min.1, p.1));
+            let max = Point(std::cmp::max(max.0, p.0), std::cmp::max(max.1, p.1));
+            (min, max)
+        }))
+    }
 
-        self.get_visibility_at(target) >= 0
+    // The farthest tile that the last cast's light passed through (visibility
+    // above 0) within half a tile of the ray from the eye along dir.
+    pub fn max_sight_along(&self, dir: Point) -> Option<Point> {
+        let eye = self.eye();
+        let norm = dir.len_l2_squared();
+        if norm == 0 { return None; }
+        let along = self.points_seen.iter().filter(|&&p| {
+            let delta = p - eye;
+            let cross = delta.cross(dir);
+            delta.dot(dir) > 0 && 4 * cross * cross <= norm && self.get_visibility_at(p) > 0
+        });
+        along.max_by_key(|&&p| (p - eye).dot(dir)).copied()
     }
 
-    pub fn compute<F: Fn(Point) -> i32>(&mut self, args: &VisionArgs<F>) {
-        self.clear(args.eye, args.initial_visibility);
-        self.seed_ranges(args.dir, None);
-        self.execute(args.eye, self.radius, &args.opacity_lookup);
+    // The last cast's visibility at each tile on the line from -> to, for
+    // rendering gradients such as light shafts through a doorway.
+    pub fn ray_profile(&self, from: Point, to: Point) -> Vec<(Point, i32)> {
+        from.line_to(to).into_iter().map(|p| (p, self.get_visibility_at(p))).collect()
     }
 
-    fn seed_ranges(&mut self, dir: Point, target: Option<Point>) {
-        let visibility = INITIAL_VISIBILITY;
+    // A rough count of the tiles a cast with this radius and dir would visit,
+    // for scheduling casts within a frame budget: the area of the disc, or of
+    // a third of it for the 120-degree directional cone.
+    pub fn estimate_cost(radius: i32, dir: Point) -> usize {
+        let r = std::cmp::max(radius, 0) as f64;
+        let disc = std::f64::consts::PI * (r * r + r) + 1.0;
+        let cost = if dir == Point::default() { disc } else { disc / 3.0 + r };
+        cost.ceil() as usize
+    }
 
-        if dir == Point::default() {
-            for transform in &TRANSFORMS {
-                let (mut min, mut max) = (Slope::new(-1, 1), Slope::new(1, 1));
+    // The visibility-weighted mean position of the last cast's seen tiles,
+    // rounded, e.g. for framing the camera. None if no tile has visibility.
+    pub fn visible_centroid(&self) -> Option<Point> {
+        let (mut x, mut y, mut total) = (0, 0, 0);
+        for &p in &self.points_seen {
+            let weight = self.get_visibility_at(p) as i64;
+            (x, y, total) = (x + weight * p.0 as i64, y + weight * p.1 as i64, total + weight);
+        }
+        if total <= 0 { return None; }
+        let mean = |sum: i64| (sum as f64 / total as f64).round() as i32;
+        Some(Point(mean(x), mean(y)))
+    }
 
-                // Skip this quadrant if the target outside it; else, filter.
-                if let Some(target) = target {
-                    let Transform([[a00, a01], [a10, a11]]) = *transform;
-                    let inverse = Transform([[a00, -a01], [-a10, a11]]);
+    // An approximate signed distance, in tiles, from each tile in the inclusive
+    // world-space rectangle bounds to the edge of the last cast's field: the
+    // negated distance to the nearest unseen tile for seen tiles, and the
+    // distance to the nearest seen tile otherwise. Only tiles in bounds count;
+    // if bounds holds no tile of the other kind, the result is ±SDF_UNBOUNDED.
+    pub fn to_sdf(&self, bounds: (Point, Point)) -> Matrix<i32> {
+        let (min, max) = bounds;
+        let size = Point(std::cmp::max(max.0 - min.0 + 1, 0), std::cmp::max(max.1 - min.1 + 1, 0));
+        let mut seen = Matrix::new(size, false);
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                let p = Point(x, y);
+                seen.set(p, self.can_see_point(p + min));
+            }
+        }
+        let mut unseen = seen.clone();
+        unseen.data.iter_mut().for_each(|x| *x = !*x);
 
-                    let Point(x, y) = inverse * target;
-                    if x == 0 || x < y.abs() { continue; }
-                    min = std::cmp::max(min, Slope::new(2 * y - 1, 2 * x));
-                    max = std::cmp::min(max, Slope::new(2 * y + 1, 2 * x));
-                }
+        let (to_seen, to_unseen) = (chamfer_distance(&seen), chamfer_distance(&unseen));
+        let data = seen.data.iter().enumerate().map(|(i, &inside)| {
+            let thirds = if inside { -to_unseen.data[i] } else { to_seen.data[i] };
+            if thirds.abs() >= CHAMFER_FAR { return thirds.signum() * SDF_UNBOUNDED; }
+            (thirds + thirds.signum()) / 3
+        }).collect();
+        Matrix::from_vec(size, data, 0).unwrap()
+    }
 
-                // If the range is still non-empty, scan it.
-                if max <= min { continue; }
-                self.prev.items.push(SlopeRange { min, max, transform, visibility });
-            }
-        } else {
-            for transform in &TRANSFORMS {
-                // Use the inverse to map dir into the right 90-degree quadrant.
-                let Transform([[a00, a01], [a10, a11]]) = *transform;
-                let inverse = Transform([[a00, -a01], [-a10, a11]]);
-                let Point(x, y) = inverse * dir;
-                let Point(lx, ly) = ROT_LEFT_ * Point(x, y);
-                let Point(rx, ry) = ROT_RIGHT * Point(x, y);
-                debug_assert!(x != 0 || y != 0);
-
-                // Casework to figure out how the dir constrains slope ranges.
-                // Here, we rely on the fact that the window is <= 180 degrees.
-                let (mut min, mut max) = (Slope::new(-1, 1), Slope::new(1, 1));
-                if x < 0 {
-                    if y == 0 { continue; }
-                    if y > 0 {
-                        if rx <= 0 { continue; }
-                        min = std::cmp::max(min, Slope::new(ry, rx));
-                    } else {
-                        if lx <= 0 { continue; }
-                        max = std::cmp::min(max, Slope::new(ly, lx));
-                    }
-                } else {
-                    if lx > 0 { max = std::cmp::min(max, Slope::new(ly, lx)); }
-                    if rx > 0 { min = std::cmp::max(min, Slope::new(ry, rx)); }
-                }
+    // The tiles within the radius of the last cast's eye that it didn't see,
+    // in world coordinates, row by row: the blind spots around the eye.
+    pub fn iter_shadow(&self) -> impl Iterator<Item = Point> + '_ {
+        let (radius, eye) = (self.radius, self.eye());
+        let range = move || -radius..=radius;
+        let square = range().flat_map(move |y| range().map(move |x| eye + Point(x, y)));
+        square.filter(move |&p| self.in_range(eye, p, self.nudge) && self.get_visibility_at(p) < 0)
+    }
 
-                // Skip this quadrant if the target outside it; else, filter.
-                if let Some(target) = target {
-                    let Point(x, y) = inverse * target;
-                    if x == 0 || x < y.abs() { continue; }
-                    min = std::cmp::max(min, Slope::new(2 * y - 1, 2 * x));
-                    max = std::cmp::min(max, Slope::new(2 * y + 1, 2 * x));
-                }
+    // For each beam of the last compute that survived to its full radius, the
+    // world positions of the tiles bounding it at that depth: the clear shots
+    // to the edge of the field. Only meaningful after a full cast, not can_see.
+    pub fn sightlines(&self) -> Vec<(Point, Point)> {
+        let depth = self.radius;
+        if self.prev.depth != depth + 1 { return vec![]; }
+        let eye = self.eye();
+        self.prev.items.iter().map(|range| {
+            let SlopeRange { min, max, transform, .. } = *range;
+            let start = div_floor(2 * min.num * depth + min.den, 2 * min.den);
+            let limit = div_ceil(2 * max.num * depth - max.den, 2 * max.den);
+            (*transform * Point(depth, start) + eye, *transform * Point(depth, limit) + eye)
+        }).collect()
+    }
 
-                // If the range is still non-empty, scan it.
-                if max <= min { continue; }
-                self.prev.items.push(SlopeRange { min, max, transform, visibility });
-            }
+    // Counts the last cast's seen tiles in each 45-degree octant around the
+    // eye, starting at +x and turning toward +y. Each octant includes the
+    // axis at its start. The eye's own tile isn't counted.
+    pub fn coverage_by_octant(&self) -> [usize; 8] {
+        let axes = [Point(1, 0), Point(1, 1), Point(0, 1), Point(-1, 1),
+                    Point(-1, 0), Point(-1, -1), Point(0, -1), Point(1, -1)];
+        let eye = self.eye();
+        let mut result = [0; 8];
+        for &p in &self.points_seen {
+            let delta = p - eye;
+            let octant = (0..8).find(|&i| {
+                axes[i].cross(delta) >= 0 && delta.cross(axes[(i + 1) % 8]) > 0
+            });
+            if let Some(i) = octant { result[i] += 1; }
         }
+        result
     }
 
-    fn execute<F: Fn(Point) -> i32>(&mut self, eye: Point, limit: i32, opacity_lookup: F) {
-        let radius = self.radius;
-        let center = Point(radius, radius);
-        let r2 = radius * radius + radius;
+    // The last cast's seen tiles with their visibility, nearest to the eye
+    // first. Cast order only approximates this: it visits tiles by depth along
+    // each quadrant's axis, so a diagonal tile can precede a nearer one.
+    pub fn seen_by_distance(&self) -> Vec<(Point, i32)> {
+        let eye = self.eye();
+        let mut result: Vec<_> =
+            self.points_seen.iter().map(|&p| (p, self.get_visibility_at(p))).collect();
+        result.sort_by_key(|&(p, _)| (p - eye).len_l2_squared());
+        result
+    }
 
-        let push = |next: &mut SlopeRanges, s: SlopeRange| {
-            if let Some(x) = next.items.last_mut() {
-                if x.max == s.min && x.visibility == s.visibility &&
-                   x.transform as *const Transform == s.transform as *const Transform {
-                    x.max = s.max;
-                    return;
-                }
-            }
-            next.items.push(s);
-        };
+    // The last cast's seen tiles, with their visibility, that are within range
+    // of the eye under the given metric. Lets one large cast answer several
+    // smaller range queries.
+    pub fn seen_within(&self, range: i32, metric: RadiusMetric)
+            -> impl Iterator<Item = (Point, i32)> + '_ {
+        let eye = self.eye();
+        self.points_seen.iter().filter(move |&&p| metric.within(p - eye, range))
+            .map(|&p| (p, self.get_visibility_at(p)))
+    }
 
-        while self.prev.depth <= limit && !self.prev.items.is_empty() {
-            let depth = self.prev.depth;
+    pub fn radius(&self) -> i32 {
+        self.radius
+    }
 
-            for range in &self.prev.items {
-                let mut prev_visibility = -1;
-                let SlopeRange { mut min, max, transform, visibility } = *range;
-                let start = div_floor(2 * min.num * depth + min.den, 2 * min.den);
-                let limit = div_ceil(2 * max.num * depth - max.den, 2 * max.den);
+    // The world position the field is currently centered on: the eye of the
+    // last cast or can_see, or (radius, radius) if nothing has been cast yet.
+    // With a projection_offset, that's the projected eye.
+    pub fn eye(&self) -> Point {
+        self.to_world(Point(self.radius, self.radius))
+    }
 
-                for width in start..=limit {
-                    let (x, y) = (depth, width);
-                    let nearby = x * x + y * y <= r2;
-                    let point = *transform * Point(x, y);
+    #[deprecated(note = "use eye instead")]
+    pub fn center_world(&self) -> Point {
+        self.eye()
+    }
 
-                    let next_visibility = (|| {
-                        if !nearby { return -1; }
-                        let opacity = opacity_lookup(point + eye);
-                        if opacity == 0 { return visibility; }
-                        if opacity >= visibility { return 0; }
-                        let r = 1.0 + (0.5 * y.abs() as f64) / (x as f64);
-                        std::cmp::max(visibility - (r * opacity as f64) as i32, 0)
-                    })();
+    // Added to a world position to get its index in the visibility Matrix.
+    pub fn offset(&self) -> Point {
+        self.offset
+    }
 
-                    if next_visibility >= 0 {
-                        let entry = self.visibility.entry_mut(point + center).unwrap();
-                        if *entry < 0 { self.points_seen.push(point + eye); }
-                        *entry = std::cmp::max(*entry, next_visibility);
-                    }
+    // Converts a world position to its index in the visibility Matrix. The
+    // result is only in bounds if the position is within radius of the eye.
+    pub fn to_local(&self, world: Point) -> Point {
+        world + self.offset
+    }
 
-                    if prev_visibility != next_visibility && prev_visibility >= 0 {
-                        let slope = Slope::new(2 * width - 1, 2 * depth);
-                        if prev_visibility > 0 {
-                            let (max, visibility) = (slope, prev_visibility);
-                            let range = SlopeRange { min, max, transform, visibility };
-                            push(&mut self.next, range);
-                        }
-                        min = slope;
-                    }
-                    prev_visibility = next_visibility;
-                }
-
-                if prev_visibility > 0 {
-                    let visibility = prev_visibility;
-                    let range = SlopeRange { min, max, transform, visibility };
-                    push(&mut self.next, range);
-                }
-            }
-
-            std::mem::swap(&mut self.prev, &mut self.next);
-            self.next.items.clear();
-            self.next.depth += 2;
-        }
+    // Converts an index in the visibility Matrix back to a world position.
+    pub fn to_world(&self, local: Point) -> Point {
+        local - self.offset
     }
-}
 
-//////////////////////////////////////////////////////////////////////////////
+    pub fn get_points_seen(&self) -> &[Point] {
+        &self.points_seen
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // The last cast's seen tiles, in world coordinates, with their visibility.
+    pub fn seen(&self) -> impl Iterator<Item = (Point, i32)> + '_ {
+        let (offset, visibility) = (self.offset, &self.visibility);
+        self.points_seen.iter().map(move |&p| (p, *visibility.entry_ref(p + offset)))
+    }
 
-    use rand::{Rng, SeedableRng};
-    use rand::rngs::StdRng;
+    // Tiles revealed by the last cast: those within its reveal_radius with
+    // line of sight, if one was set, else just the seen tiles.
+    pub fn get_points_revealed(&self) -> &[Point] {
+        match &self.revealer {
+            Some(x) if self.revealed => &x.points_seen,
+            _ => &self.points_seen,
+        }
+    }
 
-    const VISIBILITY_LOSS: i32 = VISIBILITY_LOSSES[2];
+    // The visibility of a tile seen by the last cast, or None if it's unseen.
+    pub fn visibility_at(&self, p: Point) -> Option<i32> {
+        let visibility = self.visibility.get(self.to_local(p));
+        if visibility < 0 { None } else { Some(visibility) }
+    }
 
-    fn run_fov(eye: Point, dir: Point, map: &Matrix<char>,
-               radius: i32, check_point_lookups: bool) -> Matrix<bool> {
-        // Wrapper around Vision to make it easier to test.
-        let initial_visibility = INITIAL_VISIBILITY;
-        let opacity_lookup = |p: Point| -> i32 {
-            let c = if map.contains(p) { map.get(p) } else { '#' };
-            match c {
-                '#' => INITIAL_VISIBILITY,
-                ',' => VISIBILITY_LOSS,
-                _ => 0,
-            }
-        };
-        let args = VisionArgs { eye, dir, opacity_lookup, initial_visibility };
+    // Like visibility_at, but with -1 for unseen tiles.
+    pub fn get_visibility_at(&self, p: Point) -> i32 {
+        self.visibility_at(p).unwrap_or(-1)
+    }
 
-        let mut vision = Vision::new(radius);
-        vision.compute(&args);
+    pub fn can_see_point(&self, p: Point) -> bool {
+        self.visibility_at(p).is_some()
+    }
 
-        let mut result = Matrix::new(map.size, false);
-        for y in 0..map.size.1 {
-            for x in 0..map.size.0 {
-                let p = Point(x, y);
-                result.set(p, vision.get_visibility_at(p) >= 0);
+    // The seen tiles in the same 4-connected component of passable tiles as
+    // the eye, excluding those that are visible but unreachable.
+    pub fn reachable_visible(&self, passable: &Matrix<bool>) -> Vec<Point> {
+        let eye = self.eye();
+        let mut reached = Matrix::new(passable.size, false);
+        let mut stack = if passable.contains(eye) { vec![eye] } else { vec![] };
+        reached.set(eye, true);
+        while let Some(p) = stack.pop() {
+            for dir in [Point(1, 0), Point(-1, 0), Point(0, 1), Point(0, -1)] {
+                let next = p + dir;
+                if !passable.contains(next) || !passable.get(next) { continue; }
+                if reached.get(next) { continue; }
+                reached.set(next, true);
+                stack.push(next);
             }
         }
+        self.points_seen.iter().copied().filter(|&p| reached.get(p)).collect()
+    }
 
-        // Check that point lookups are consistent with the full computation.
-        if check_point_lookups {
-            for y in 0..map.size.1 {
-                for x in 0..map.size.0 {
-                    let p = Point(x, y);
-                    assert!(result.get(p) == vision.can_see(&args, p));
+    // Groups the last cast's seen tiles into 4-connected regions of equal
+    // visibility, such as the bands of light fading through grass.
+    pub fn regions(&self) -> Vec<(i32, Vec<Point>)> {
+        let dirs = [Point(1, 0), Point(-1, 0), Point(0, 1), Point(0, -1)];
+        let mut grouped = Matrix::new(self.visibility.size, false);
+        let mut result = vec![];
+        for &start in &self.points_seen {
+            if grouped.get(self.to_local(start)) { continue; }
+            let visibility = self.get_visibility_at(start);
+            grouped.set(self.to_local(start), true);
+            let (mut region, mut i) = (vec![start], 0);
+            while i < region.len() {
+                let p = region[i];
+                i += 1;
+                for &dir in &dirs {
+                    let next = p + dir;
+                    let index = self.to_local(next);
+                    if !grouped.contains(index) || grouped.get(index) { continue; }
+                    if self.visibility.get(index) != visibility { continue; }
+                    grouped.set(index, true);
+                    region.push(next);
                 }
             }
+            result.push((visibility, region));
         }
         result
     }
 
-    fn test_fov(input: &[&str], expected: &[&str]) {
-        // Convert the input grid into a map.
-        let height = input.len();
-        let width = input[0].len();
-        let mut map = Matrix::new(Point(width as i32, height as i32), '#');
-        let mut eye = None;
-        let mut target = None;
+    // The number of 4-neighbor edges between a tile that light passed through
+    // (visibility above 0) and one that it didn't reach or was stopped by,
+    // for "how exposed am I" heuristics.
+    pub fn perimeter(&self) -> usize {
+        let dirs = [Point(1, 0), Point(-1, 0), Point(0, 1), Point(0, -1)];
+        let lit = self.points_seen.iter().filter(|&&p| self.get_visibility_at(p) > 0);
+        lit.map(|&p| dirs.iter().filter(|&&d| self.get_visibility_at(p + d) <= 0).count()).sum()
+    }
 
-        for (y, row) in input.iter().enumerate() {
-            for (x, c) in row.chars().enumerate() {
-                let point = Point(x as i32, y as i32);
-                map.set(point, c);
+    // The visibility at p relative to the eye's, in [0, 1]. Unseen tiles are
+    // dark, as are all tiles if the eye's initial visibility was 0.
+    pub fn brightness_at(&self, p: Point) -> f32 {
+        let eye = self.visibility.get(Point(self.radius, self.radius));
+        if eye <= 0 { return 0.0; }
+        (std::cmp::max(self.get_visibility_at(p), 0) as f32 / eye as f32).min(1.0)
+    }
 
-                if c == '@' {
-                    assert!(eye.is_none());
-                    eye = Some(point);
-                } else if c == 'X' {
-                    assert!(target.is_none());
-                    target = Some(point);
-                }
-            }
-        }
+    // Bilinearly interpolates brightness_at between the four tiles around p,
+    // where each tile's center is at its integer coordinates.
+    pub fn brightness_bilinear(&self, p: (f32, f32)) -> f32 {
+        let (x, y) = (p.0.floor(), p.1.floor());
+        let (fx, fy) = (p.0 - x, p.1 - y);
+        let at = |dx: i32, dy: i32| self.brightness_at(Point(x as i32 + dx, y as i32 + dy));
+        let top = at(0, 0) * (1.0 - fx) + at(1, 0) * fx;
+        let bottom = at(0, 1) * (1.0 - fx) + at(1, 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
 
-        // Get the FOV result and compare it to the expected value.
-        let eye = eye.unwrap();
-        let dir = if let Some(x) = target { x - eye } else { Point::default() };
-        let visible = run_fov(eye, dir, &map, map.size.0 + map.size.1, true);
-        let result = show_fov(eye, &map, &visible);
-        if expected != result {
-            panic!("\nExpected:\n&{:#?}\n\nGot:\n&{:#?}", expected, result);
+    // Explored memory lives in a caller-owned Matrix in world coordinates, so
+    // it can be sized to the whole map rather than to the vision radius.
+    pub fn mark_explored(&self, explored: &mut Matrix<bool>) {
+        for &point in self.get_points_revealed() {
+            explored.set(point, true);
         }
     }
 
-    fn show_fov(eye: Point, map: &Matrix<char>, visible: &Matrix<bool>) -> Vec<String> {
-        let mut result = Vec::new();
-        for y in 0..map.size.1 {
-            let mut row = String::new();
-            for x in 0..map.size.0 {
-                let p = Point(x as i32, y as i32);
-                let (is_eye, is_visible) = (p == eye, visible.get(p));
-                let c = if is_eye { '@' } else if !is_visible { '%' } else { map.get(p) };
-                row.push(c);
+    // The fraction of passable tiles that have been explored, in [0, 1]. Maps
+    // with no passable tiles count as entirely unexplored.
+    pub fn explored_fraction(explored: &Matrix<bool>, passable: &Matrix<bool>) -> f32 {
+        let (mut total, mut seen) = (0, 0);
+        for y in 0..passable.size.1 {
+            for x in 0..passable.size.0 {
+                let p = Point(x, y);
+                if !passable.get(p) { continue; }
+                total += 1;
+                if explored.get(p) { seen += 1; }
             }
-            result.push(row);
         }
-        result
+        if total == 0 { 0.0 } else { seen as f32 / total as f32 }
     }
 
-    #[test]
-    fn test_empty() {
-        test_fov(&[
-            "@...",
-            "....",
-            "....",
-        ], &[
-            "@...",
-            "....",
-            "....",
-        ]);
+    // Samples line of sight from the eye's center to the four corners of the
+    // target tile (inset slightly, so rays don't graze neighboring corners) and
+    // returns true if at least min_coverage of them are unobstructed. A ray is
+    // obstructed once the opacity it passes through reaches initial_visibility.
+    pub fn is_visible_aa<F: OpacitySource>(
+            &self, args: &VisionArgs<F>, target: Point, min_coverage: f32) -> bool {
+        self.aa_coverage(args, target) >= min_coverage
     }
 
-    #[test]
-    fn test_single_pillar() {
-        test_fov(&[
-            "@...",
-            ".#..",
-            "....",
-        ], &[
-            "@...",
-            ".#..",
-            "..%%",
-        ]);
+    // A cheap approximation to a full cast: traces `steps` evenly-spaced rays
+    // out to the vision radius and returns the distinct opaque tiles they hit
+    // first, in angular order. Partial transparency is ignored.
+    pub fn cast_walls<F: OpacitySource>(&self, args: &VisionArgs<F>, steps: u32) -> Vec<Point> {
+        let mut result: Vec<Point> = vec![];
+        let from = (args.eye.0 as f64, args.eye.1 as f64);
+        let length = self.radius as f64 + 0.5;
+        let nudge = args.options.boundary_r2_nudge(self.radius);
+
+        for i in 0..steps {
+            let angle = std::f64::consts::TAU * i as f64 / steps as f64;
+            let to = (from.0 + length * angle.cos(), from.1 + length * angle.sin());
+            let mut hit = None;
+            trace_ray(from, to, |p| {
+                if p == args.eye { return true; }
+                if !self.in_range(args.eye, p, nudge) { return false; }
+                if args.opacity_lookup.opacity(p) < args.initial_visibility { return true; }
+                hit = Some(p);
+                false
+            });
+            let Some(p) = hit else { continue; };
+            if !result.contains(&p) { result.push(p); }
+        }
+        result
     }
 
-    #[test]
-    fn test_wall_with_gap() {
-        test_fov(&[
-            "@....",
-            ".....",
-            "..#..",
-            ".....",
-            "..#..",
-        ], &[
-            "@....",
-            ".....",
-            "..#..",
-            "...%.",
-            "..#.%",
-        ]);
+    fn aa_coverage<F: OpacitySource>(&self, args: &VisionArgs<F>, target: Point) -> f32 {
+        if args.eye == target { return 1.0; }
+
+        let nudge = args.options.boundary_r2_nudge(self.radius);
+        if !self.in_range(args.eye, target, nudge) { return 0.0; }
+
+        const INSET: f64 = 0.4;
+        let from = (args.eye.0 as f64, args.eye.1 as f64);
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)];
+        let clear = corners.iter().filter(|&&(dx, dy)| {
+            let to = (target.0 as f64 + dx * INSET, target.1 as f64 + dy * INSET);
+            let mut total = 0;
+            trace_ray(from, to, |p| {
+                if p == args.eye || p == target { return true; }
+                total += args.opacity_lookup.opacity(p);
+                total < args.initial_visibility
+            })
+        }).count();
+        clear as f32 / corners.len() as f32
     }
 
-    #[test]
-    fn test_diagonal_wall() {
-        test_fov(&[
-            "@....",
-            ".....",
-            "..#..",
-            "...#.",
-            "....#",
-        ], &[
-            "@....",
-            ".....",
-            "..#..",
-            "...%.",
-            "....%",
-        ]);
+    // Additively blends this vision's visibility into a world-space light map.
+    // Since addition is invertible, a source can later be removed without
+    // recomputing the others; this does not hold for max-blended light maps.
+    pub fn add_to_light(&self, light: &mut Matrix<i32>) {
+        for &point in &self.points_seen {
+            let Some(entry) = light.entry_mut(point) else { continue; };
+            *entry += self.get_visibility_at(point);
+        }
     }
 
-    #[test]
-    fn test_near_45() {
-        test_fov(&[
-            "@...",
-            "....",
-            "..#.",
-            "....",
-        ], &[
-            "@...",
-            "....",
-            "..#.",
-            "...%",
-        ]);
+    pub fn subtract_from_light(&self, light: &mut Matrix<i32>) {
+        for &point in &self.points_seen {
+            let Some(entry) = light.entry_mut(point) else { continue; };
+            *entry -= self.get_visibility_at(point);
+        }
     }
 
-    #[test]
-    fn test_gaps() {
-        test_fov(&[
-            "..........#",
-            "..........#",
-            "..........#",
-            "......#...#",
-            "..##..#...#",
-            "..........#",
-            "...@......#",
-            "......#...#",
-            "##....#...#",
-            "..........#",
-            "####..##..#",
-        ], &[
-            "%%%%%....%%",
-            "%%%%....%%%",
-            ".%%%...%%%%",
-            "..%%..#%%.#",
-            "..##..#...#",
-            "..........#",
-            "...@......#",
-            "......#...#",
-            "##....#%%%%",
-            "%.......%%%",
-            "####..##.%%",
-        ]);
+    // Like add_to_light, but saturating, with each cell clamped to at most cap.
+    // Unlike add_to_light, this can't be undone by subtract_from_light.
+    pub fn accumulate_into_capped(&self, light: &mut Matrix<i32>, cap: i32) {
+        for &point in &self.points_seen {
+            let Some(entry) = light.entry_mut(point) else { continue; };
+            *entry = std::cmp::min(entry.saturating_add(self.get_visibility_at(point)), cap);
+        }
     }
 
-    #[test]
-    fn test_near_wall() {
-        test_fov(&[
-            "...............",
-            ".#############.",
-            ".#@..........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#############.",
-            "...............",
-        ], &[
-            "%%%%%%%%%%%%%%%",
-            "%#############%",
-            "%#@..........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#############%",
-            "%%%%%%%%%%%%%%%",
-        ]);
+    // Adds intensity * brightness_at for each seen tile into a world-space HDR
+    // light map, without clamping: overlapping sources can exceed 1.0, so the
+    // caller should tone-map the result before display.
+    pub fn accumulate_hdr_into(&self, light: &mut Matrix<f32>, intensity: f32) {
+        for &point in &self.points_seen {
+            let Some(entry) = light.entry_mut(point) else { continue; };
+            *entry += intensity * self.brightness_at(point);
+        }
     }
 
+    // Decays every cell of a world-space trail, then brightens the cells seen
+    // by the last cast, both saturating. The decay touches the whole trail, so
+    // this is O(map size) per call rather than O(points seen).
+    pub fn paint_decay(&self, trail: &mut Matrix<u8>, add: u8, decay: u8) {
+        for x in &mut trail.data {
+            *x = x.saturating_sub(decay);
+        }
+        for &point in &self.points_seen {
+            let Some(entry) = trail.entry_mut(point) else { continue; };
+            *entry = entry.saturating_add(add);
+        }
+    }
 
-    #[test]
-    fn test_large() {
-        test_fov(&[
-            "...............",
-            ".#############.",
-            ".#...........#.",
-            ".#...........#.",
-            ".#.......#...#.",
+    pub fn clear(&mut self, pos: Point, visibility: i32) {
+        // Sparse clear optimization. The dense clear has much better constant
+        // factors so we only switch over when it's sufficiently sparse.
+        //
+        // execute pushes each tile to points_seen as soon as it's written, so
+        // this remains correct even if a previous cast panicked partway, e.g.
+        // in a user's opacity_lookup. Everything else is reset below.
+        if self.visibility.data.len() < 16 * self.points_seen.len() {
+            self.visibility.fill(-1);
+        } else {
+            for &point in &self.points_seen {
+                debug_assert!(self.get_visibility_at(point) >= 0);
+                self.visibility.set(self.to_local(point), -1);
+            }
+            debug_assert!(self.visibility.data.iter().all(|&x| x == -1));
+        }
+
+        let center = Point(self.radius, self.radius);
+        self.offset = center - pos;
+        self.points_seen.clear();
+        self.nudge = 0;
+
+        self.visibility.set(center, visibility);
+        self.points_seen.push(pos);
+        self.tagged = false;
+        self.revealed = false;
+
+        self.prev.depth = 1;
+        self.next.depth = 2;
+        self.prev.items.clear();
+        self.next.items.clear();
+    }
+
+    // With radius 0, the eye can see its own tile and nothing else. Both can_see
+    // and compute agree on this: the disc check rejects every other target.
+    pub fn can_see<F: OpacitySource>(&mut self, args: &VisionArgs<F>, target: Point) -> bool {
+        if args.eye == target { return true; }
+        if !self.seed_target(args, target) { return false; }
+
+        let rule = args.options.target_rule;
+        if rule == TargetRule::AnyCorner {
+            let limit = (target - args.eye).len_l1();
+            let lookup = lookup_span(&args.opacity_lookup);
+            self.execute(&args.options, args.eye, limit, lookup, |_, _| {});
+            return self.get_visibility_at(target + args.options.projection_offset) >= 0;
+        }
+
+        // Cast up to the target's row, then check the beams that reach it.
+        // The target is at depth x in each seeded quadrant's frame.
+        let seeds = self.prev.items.clone();
+        let Point(dx, dy) = target - args.eye;
+        let limit = std::cmp::max(dx.abs(), dy.abs()) - 1;
+        self.execute(&args.options, args.eye, limit, lookup_span(&args.opacity_lookup), |_, _| {});
+        if self.prev.depth != limit + 1 { return false; }
+
+        let beams = &self.prev.items;
+        let mut result = !seeds.is_empty();
+        for seed in &seeds {
+            let transform = seed.transform;
+            let Transform([[a00, a01], [a10, a11]]) = *transform;
+            let Point(x, y) = Transform([[a00, -a01], [-a10, a11]]) * (target - args.eye);
+            let mut beams = beams.iter().filter(|b| std::ptr::eq(b.transform, transform));
+            result &= match rule {
+                TargetRule::AnyCorner => unreachable!(),
+                TargetRule::Center => {
+                    let center = Slope::new(y, x);
+                    beams.any(|b| b.min <= center && center <= b.max)
+                }
+                TargetRule::AllCorners => {
+                    let mut covered = seed.min;
+                    for b in beams {
+                        if b.min <= covered && covered < b.max { covered = b.max; }
+                    }
+                    covered >= seed.max
+                }
+            };
+        }
+        result
+    }
+
+    // Runs the same scan as can_see with the default TargetRule, and reports
+    // what it found: the target's visibility, or -1 if it's unseen, and the
+    // tiles with any opacity that the scan saw on the way, in scan order.
+    pub fn explain<F: OpacitySource>(
+            &mut self, args: &VisionArgs<F>, target: Point) -> VisibilityExplanation {
+        let delta = target - args.eye;
+        let quadrant = TRANSFORMS.iter().position(|&transform| {
+            let Transform([[a00, a01], [a10, a11]]) = transform;
+            let Point(x, y) = Transform([[a00, -a01], [-a10, a11]]) * delta;
+            x > 0 && x >= y.abs()
+        }).unwrap_or(0);
+
+        let blockers = vec![];
+        if args.eye == target {
+            let visibility = args.initial_visibility;
+            return VisibilityExplanation { visible: true, visibility, blockers, quadrant };
+        }
+        if !self.seed_target(args, target) {
+            return VisibilityExplanation { visible: false, visibility: -1, blockers, quadrant };
+        }
+
+        let limit = delta.len_l1();
+        self.execute(&args.options, args.eye, limit, lookup_span(&args.opacity_lookup), |_, _| {});
+        let projection = args.options.projection_offset;
+        let visibility = self.get_visibility_at(target + projection);
+        let blockers = self.points_seen.iter().map(|&p| p - projection).filter(|&p| {
+            p != args.eye && p != target && args.opacity_lookup.opacity(p) > 0
+        }).collect();
+        VisibilityExplanation { visible: visibility >= 0, visibility, blockers, quadrant }
+    }
+
+    // Resets the field and seeds the beams for a scan toward target. Returns
+    // false, without seeding, if target is out of range or excluded.
+    fn seed_target<F: OpacitySource>(&mut self, args: &VisionArgs<F>, target: Point) -> bool {
+        let nudge = args.options.boundary_r2_nudge(self.radius);
+        if !self.in_range(args.eye, target, nudge) { return false; }
+
+        let behind = args.options.exclude_behind && args.dir != Point::default();
+        if behind && (target - args.eye).dot(args.dir) < 0 { return false; }
+
+        self.clear(args.eye + args.options.projection_offset, args.initial_visibility);
+        let dir = if behind { Point::default() } else { args.dir };
+        self.seed_ranges(dir, Some(target - args.eye), args.initial_visibility);
+        true
+    }
+
+    // Whether the tile p is within this Vision's radius of eye, with the
+    // Euclidean disc's r2 adjusted by nudge.
+    fn in_range(&self, eye: Point, p: Point, nudge: i64) -> bool {
+        within_radius(self.metric, eye, p, self.radius, nudge)
+    }
+
+    // For a stealth indicator: whether any of the viewers, each an (eye, dir)
+    // pair, can see me within half_angle radians of its dir, as approximated
+    // for compute_from_slit. A viewer with no dir sees all around. Each check
+    // is a cast narrowed to me, as for can_see; the field is left holding the
+    // last one's.
+    pub fn am_i_seen<F: OpacitySource>(
+            &mut self, viewers: &[(Point, Point)], me: Point, half_angle: f64,
+            opacity_lookup: F, initial_visibility: i32, options: &CastOptions) -> bool {
+        let (left, right) = cone_rotations(half_angle);
+        let nudge = options.boundary_r2_nudge(self.radius);
+        let projection = options.projection_offset;
+        viewers.iter().any(|&(eye, dir)| {
+            let delta = me - eye;
+            if delta == Point::default() { return true; }
+            if !self.in_range(eye, me, nudge) { return false; }
+
+            self.clear(eye + projection, initial_visibility);
+            if dir == Point::default() {
+                self.seed_ranges(dir, Some(delta), initial_visibility);
+            } else {
+                self.seed_cone(dir, left, right, Some(delta), initial_visibility);
+            }
+            if self.prev.items.is_empty() { return false; }
+            let fetch = lookup_span(&opacity_lookup);
+            self.execute(options, eye, delta.len_l1(), fetch, |_, _| {});
+            self.get_visibility_at(me + projection) >= 0
+        })
+    }
+
+    // The eye always sees its own tile with initial_visibility; opacity_lookup
+    // is never queried there, so an eye on an opaque or out-of-bounds tile
+    // still casts outward from its center.
+    pub fn compute<F: OpacitySource>(&mut self, args: &VisionArgs<F>) {
+        self.compute_with_terminations(args, |_, _| {});
+    }
+
+    // Like compute, but calls on_terminate(tile, quadrant) wherever a beam is
+    // extinguished: at the first tile of each run that cuts visibility to 0.
+    pub fn compute_with_terminations<F: OpacitySource, T: FnMut(Point, &'static Transform)>(
+            &mut self, args: &VisionArgs<F>, on_terminate: T) {
+        self.compute_seeded(args, None, on_terminate);
+    }
+
+    // The body of compute, with optional precomputed beams for args.dir. They
+    // aren't used for exclude_behind, which seeds the cast differently.
+    fn compute_seeded<F: OpacitySource, T: FnMut(Point, &'static Transform)>(
+            &mut self, args: &VisionArgs<F>, seeds: Option<&[SlopeRange]>, on_terminate: T) {
+        let behind = args.options.exclude_behind && args.dir != Point::default();
+        self.clear(args.eye + args.options.projection_offset, args.initial_visibility);
+        let visibility = args.initial_visibility;
+        match seeds {
+            Some(seeds) if !behind => {
+                self.prev.items.extend(seeds.iter().map(|&x| SlopeRange { visibility, ..x }));
+            }
+            _ => {
+                let dir = if behind { Point::default() } else { args.dir };
+                self.seed_ranges(dir, None, visibility);
+            }
+        }
+        let fetch = lookup_span(&args.opacity_lookup);
+        self.execute(&args.options, args.eye, self.radius, fetch, on_terminate);
+
+        if behind { self.drop_behind(args.dir); }
+
+        let Some(radius) = args.options.reveal_radius else { return; };
+        if radius <= self.radius { return; }
+        let metric = self.metric;
+        let revealer = match &mut self.revealer {
+            Some(x) if x.radius == radius && x.metric == metric => x,
+            x => {
+                let builder = VisionBuilder::new().radius(radius).metric(metric);
+                x.insert(Box::new(builder.build().unwrap()))
+            }
+        };
+        revealer.clear(args.eye + args.options.projection_offset, args.initial_visibility);
+        let dir = if behind { Point::default() } else { args.dir };
+        revealer.seed_ranges(dir, None, args.initial_visibility);
+        let fetch = lookup_span(&args.opacity_lookup);
+        revealer.execute(&args.options, args.eye, radius, fetch, |_, _| {});
+        if behind { revealer.drop_behind(args.dir); }
+        self.revealed = true;
+    }
+
+    // Unsees every tile of the last cast that's strictly behind the eye.
+    fn drop_behind(&mut self, dir: Point) {
+        let (eye, offset) = (self.eye(), self.offset);
+        let visibility = &mut self.visibility;
+        self.points_seen.retain(|&p| {
+            if (p - eye).dot(dir) >= 0 { return true; }
+            visibility.set(p + offset, -1);
+            false
+        });
+    }
+
+    // Computes the initial beams for casts facing dir, for compute_with_seed.
+    // Leaves the last cast's results untouched. The beams don't depend on the
+    // Vision, so a template can be shared by any Visions.
+    pub fn precompute_seed(&mut self, dir: Point) -> SeedTemplate {
+        let saved = std::mem::take(&mut self.prev.items);
+        self.seed_ranges(dir, None, INITIAL_VISIBILITY);
+        SeedTemplate { dir, ranges: std::mem::replace(&mut self.prev.items, saved) }
+    }
+
+    // Like compute, but reuses the template's initial beams instead of
+    // recomputing them. Panics if args.dir isn't the template's dir.
+    pub fn compute_with_seed<F: OpacitySource>(
+            &mut self, args: &VisionArgs<F>, template: &SeedTemplate) {
+        assert!(args.dir == template.dir);
+        self.compute_seeded(args, Some(&template.ranges), |_, _| {});
+    }
+
+    // Like compute, but with a fractional radius of at most this Vision's, so
+    // that a guttering torch fades smoothly. Tiles in the disc of radius
+    // ceil(radius) but not floor(radius) have visibility scaled by the
+    // fractional part; tiles beyond it aren't seen. Discs use the metric.
+    pub fn compute_faded_radius<F: OpacitySource>(
+            &mut self, args: &VisionArgs<F>, radius: f32) {
+        assert!(0.0 <= radius && radius <= self.radius as f32);
+        self.compute(args);
+
+        let (inner, outer) = (radius.floor() as i32, radius.ceil() as i32);
+        let (eye, fraction, metric) = (self.eye(), radius.fract(), self.metric);
+        let (offset, visibility) = (self.offset, &mut self.visibility);
+        self.points_seen.retain(|&p| {
+            if within_radius(metric, eye, p, inner, 0) { return true; }
+            let entry = visibility.entry_mut(p + offset).unwrap();
+            if !within_radius(metric, eye, p, outer, 0) {
+                *entry = -1;
+                return false;
+            }
+            *entry = (*entry as f32 * fraction).round() as i32;
+            true
+        });
+    }
+
+    // Like compute, but with each tile's opacity interpolated by t between its
+    // opacity in args.opacity_lookup and in b, rounded, e.g. to fade between
+    // the FOVs for two frames of dissipating smoke. t is clamped to [0, 1].
+    pub fn compute_blended_opacity<A: OpacitySource, B: OpacitySource>(
+            &mut self, args: &VisionArgs<A>, b: B, t: f32) {
+        let t = t.clamp(0.0, 1.0);
+        let opacity_lookup = |p: Point| {
+            let (a, b) = (args.opacity_lookup.opacity(p) as f32, b.opacity(p) as f32);
+            (a + (b - a) * t).round() as i32
+        };
+        let (eye, dir, initial_visibility) = (args.eye, args.dir, args.initial_visibility);
+        let options = args.options.clone();
+        self.compute(&VisionArgs { eye, dir, opacity_lookup, initial_visibility, options });
+    }
+
+    // Debug-only: runs compute, then checks its seen set against a slow,
+    // independent brute force and panics with the differences. Only binary
+    // opacity is supported: any positive opacity is treated as a wall. Casts
+    // with a dir, with non-default options, or by a Vision with a non-Euclidean
+    // metric are not checked.
+    #[cfg(debug_assertions)]
+    pub fn compute_validated<F: OpacitySource>(&mut self, args: &VisionArgs<F>) {
+        self.compute(args);
+        let options = &args.options;
+        if self.metric != RadiusMetric::Euclidean {
+            return;
+        }
+        if args.dir != Point::default() || options.wall_bleed != 0 ||
+           options.valid_region.is_some() || !options.transparent_override.is_empty() ||
+           options.see_over_height != 0 || options.min_range_width != 0 ||
+           options.projection_offset != Point::default() || options.residual_on_equal ||
+           options.diffraction != 0 || options.clear_first_ring ||
+           options.boundary_epsilon != 0 || options.merge_tolerance != 0 {
+            return;
+        }
+
+        let opaque = |p: Point| args.opacity_lookup.opacity(p + args.eye) > 0;
+        let mut diffs = vec![];
+        for y in -self.radius..=self.radius {
+            for x in -self.radius..=self.radius {
+                let p = Point(x, y);
+                let expected = brute_force_visible(opaque, self.radius, p);
+                let actual = self.get_visibility_at(p + args.eye) >= 0;
+                if expected != actual { diffs.push((p + args.eye, expected)); }
+            }
+        }
+        if !diffs.is_empty() {
+            panic!("FOV from {:?} disagrees with brute force (tile, expected): {:?}",
+                   args.eye, diffs);
+        }
+    }
+
+    // Per-frame fog-of-war update in world coordinates: tiles seen by the last
+    // cast become Remembered, then tiles seen by this one become Visible. Only
+    // the previous seen set is touched, so the fog must not have been marked
+    // Visible by anything but this Vision since its last cast.
+    pub fn compute_fog<F: OpacitySource>(
+            &mut self, args: &VisionArgs<F>, fog: &mut Matrix<VisibilityState>) {
+        for &point in &self.points_seen {
+            let Some(entry) = fog.entry_mut(point) else { continue; };
+            if *entry == VisibilityState::Visible { *entry = VisibilityState::Remembered; }
+        }
+        self.compute(args);
+        for &point in &self.points_seen {
+            fog.set(point, VisibilityState::Visible);
+        }
+    }
+
+    // Like compute, then fills the inclusive world-space rectangle bounds of
+    // mask with seen_value for seen tiles and unseen_value elsewhere, as for a
+    // darkness overlay. Cells of mask outside bounds are left unchanged.
+    pub fn compute_mask<F: OpacitySource>(
+            &mut self, args: &VisionArgs<F>, mask: &mut Matrix<u8>,
+            seen_value: u8, unseen_value: u8, bounds: (Point, Point)) {
+        self.compute(args);
+        let (min, max) = bounds;
+        for y in std::cmp::max(min.1, 0)..=std::cmp::min(max.1, mask.size.1 - 1) {
+            for x in std::cmp::max(min.0, 0)..=std::cmp::min(max.0, mask.size.0 - 1) {
+                let p = Point(x, y);
+                let seen = self.get_visibility_at(p) >= 0;
+                mask.set(p, if seen { seen_value } else { unseen_value });
+            }
+        }
+    }
+
+    // Like compute, but moves the results out rather than keeping them, so the
+    // caller can hold onto them while reusing this Vision. Each call allocates
+    // a fresh visibility matrix to replace the one moved out.
+    pub fn compute_result<F: OpacitySource>(&mut self, args: &VisionArgs<F>) -> VisionResult {
+        self.compute(args);
+        let fresh = Matrix::new(self.visibility.size, -1);
+        VisionResult {
+            radius: self.radius,
+            offset: self.offset,
+            points_seen: std::mem::take(&mut self.points_seen),
+            visibility: std::mem::replace(&mut self.visibility, fresh),
+        }
+    }
+
+    // Like compute, but with binary visibility: tiles opaque in opacity_lookup
+    // block entirely, while grass never does. Instead, the grass opacity each
+    // beam crosses is summed into density, a caller-owned world-space grid,
+    // for each seen non-wall tile. As in compute, oblique beams lose more.
+    pub fn compute_with_grass<F: OpacitySource, G: Fn(Point) -> i32>(
+            &mut self, args: &VisionArgs<F>, grass: G, density: &mut Matrix<i32>) {
+        // Cast with a visibility no amount of grass can exhaust. The loss from
+        // that visibility at each tile is then the grass crossed to reach it.
+        let total = i32::MAX / 2;
+        let opacity_lookup = |p: Point| {
+            if args.opacity_lookup.opacity(p) > 0 { total } else { grass(p) }
+        };
+        let mut options = args.options.clone();
+        options.falloff = Falloff::Subtractive;
+        self.clear(args.eye + options.projection_offset, total);
+        self.seed_ranges(args.dir, None, total);
+        self.execute(&options, args.eye, self.radius, lookup_span(&opacity_lookup), |_, _| {});
+
+        for &point in &self.points_seen {
+            let visibility = self.get_visibility_at(point);
+            if visibility == 0 { continue; }
+            density.set(point, total - visibility);
+            self.visibility.set(self.to_local(point), args.initial_visibility);
+        }
+    }
+
+    // Like compute, but also records in dirs, a caller-owned world-space
+    // grid, the direction light traveled to reach each seen tile. From a point
+    // source, that's simply the offset from the eye.
+    pub fn compute_with_light_dir<F: OpacitySource>(
+            &mut self, args: &VisionArgs<F>, dirs: &mut Matrix<Point>) {
+        self.compute(args);
+        let eye = self.eye();
+        for &point in &self.points_seen {
+            dirs.set(point, point - eye);
+        }
+    }
+
+    // Like compute, but also records a caller-defined tag byte for each seen
+    // tile, for distinctions opacity can't express (glass vs. open floor).
+    pub fn compute_tagged<F: OpacitySource, T: Fn(Point) -> u8>(
+            &mut self, args: &VisionArgs<F>, tag: T) {
+        self.compute(args);
+        if self.tags.size != self.visibility.size {
+            self.tags = Matrix::new(self.visibility.size, 0);
+        }
+        for &point in &self.points_seen {
+            self.tags.set(self.to_local(point), tag(point));
+        }
+        self.tagged = true;
+    }
+
+    pub fn tag_at(&self, p: Point) -> Option<u8> {
+        if !self.tagged || self.get_visibility_at(p) < 0 { return None; }
+        Some(self.tags.get(self.to_local(p)))
+    }
+
+    // The seen tiles for directional casts facing north, east, south, and
+    // west, in that order, as for a turret that may face any of them. Leaves
+    // the Vision holding the results of the west-facing cast.
+    pub fn compute_cardinals<F: OpacitySource>(
+            &mut self, eye: Point, opacity_lookup: F, initial_visibility: i32,
+            options: &CastOptions) -> [Vec<Point>; 4] {
+        let (dir, options) = (Point::default(), options.clone());
+        let mut args = VisionArgs { eye, dir, opacity_lookup, initial_visibility, options };
+        [Point(0, -1), Point(1, 0), Point(0, 1), Point(-1, 0)].map(|dir| {
+            args.dir = dir;
+            self.compute(&args);
+            self.points_seen.clone()
+        })
+    }
+
+    // The union of the tiles seen from each point on the path, facing dir,
+    // without repeats, e.g. to preview what a move will reveal. Leaves the
+    // Vision holding the results of the cast from the path's last point.
+    pub fn swept_fov<F: OpacitySource>(
+            &mut self, path: &[Point], dir: Point, opacity_lookup: F, initial_visibility: i32,
+            options: &CastOptions) -> Vec<Point> {
+        let (eye, options) = (Point::default(), options.clone());
+        let mut args = VisionArgs { eye, dir, opacity_lookup, initial_visibility, options };
+        let (mut seen, mut result) = (HashSet::new(), vec![]);
+        for &eye in path {
+            args.eye = eye;
+            self.compute(&args);
+            result.extend(self.points_seen.iter().filter(|&&p| seen.insert(p)));
+        }
+        result
+    }
+
+    // Approximates light from a segment of emitters, such as a doorway, by
+    // casting from each tile on the segment from a to b, facing normal, and
+    // returning each tile seen with its max visibility across those casts.
+    // Leaves the Vision holding the results of the cast from b.
+    pub fn compute_from_segment<F: OpacitySource>(
+            &mut self, a: Point, b: Point, normal: Point, opacity_lookup: F,
+            initial_visibility: i32, options: &CastOptions) -> Vec<(Point, i32)> {
+        let (eye, options) = (a, options.clone());
+        let mut args = VisionArgs { eye, dir: normal, opacity_lookup, initial_visibility, options };
+        let mut index: HashMap<Point, usize> = HashMap::new();
+        let mut result: Vec<(Point, i32)> = vec![];
+        for eye in a.line_to(b) {
+            args.eye = eye;
+            self.compute(&args);
+            for &p in &self.points_seen {
+                let visibility = self.get_visibility_at(p);
+                if let Some(&i) = index.get(&p) {
+                    result[i].1 = std::cmp::max(result[i].1, visibility);
+                    continue;
+                }
+                index.insert(p, result.len());
+                result.push((p, visibility));
+            }
+        }
+        result
+    }
+
+    // Casts light from a slit, which emits only within half_angle radians of
+    // slit_normal. Angles are clamped to [0, pi / 2], and are approximated by
+    // rotations with integer entries, so cone edges are accurate to ~1 degree.
+    pub fn compute_from_slit<F: OpacitySource>(
+            &mut self, slit_center: Point, slit_normal: Point, half_angle: f64, opacity_lookup: F,
+            initial_visibility: i32, options: &CastOptions) {
+        let (left, right) = cone_rotations(half_angle);
+        self.clear(slit_center + options.projection_offset, initial_visibility);
+        if slit_normal == Point::default() { return; }
+        self.seed_cone(slit_normal, left, right, None, initial_visibility);
+        let fetch = lookup_span(&opacity_lookup);
+        self.execute(options, slit_center, self.radius, fetch, |_, _| {});
+    }
+
+    // A directional cast for a guard tracking a target, facing its last known
+    // position. The usual 120-degree cone is widened by margin radians on each
+    // side, up to 180 degrees, for uncertainty in where the target went.
+    pub fn compute_tracking<F: OpacitySource>(
+            &mut self, eye: Point, last_known: Point, margin: f64, opacity_lookup: F,
+            initial_visibility: i32, options: &CastOptions) {
+        let dir = heading(eye, last_known);
+        self.clear(eye + options.projection_offset, initial_visibility);
+        if dir == Point::default() || margin <= 0.0 {
+            self.seed_ranges(dir, None, initial_visibility);
+        } else {
+            let (left, right) = cone_rotations(std::f64::consts::FRAC_PI_3 + margin);
+            self.seed_cone(dir, left, right, None, initial_visibility);
+        }
+        let fetch = lookup_span(&opacity_lookup);
+        self.execute(options, eye, self.radius, fetch, |_, _| {});
+    }
+
+    // Like compute, but opacities are fetched a span at a time. Each call to
+    // row_fetch(start, step, out) must fill out[i] with the opacity of the tile
+    // at start + i * step. The step is always a unit vector along a world row
+    // or column, so callers backed by a Matrix can copy row slices directly.
+    pub fn compute_row_opacity<R: Fn(Point, Point, &mut [i32])>(
+            &mut self, eye: Point, dir: Point, row_fetch: R, initial_visibility: i32) {
+        self.clear(eye, initial_visibility);
+        self.seed_ranges(dir, None, initial_visibility);
+        self.execute(&CastOptions::default(), eye, self.radius, row_fetch, |_, _| {});
+    }
+
+    // Seeds the beams for a cast facing dir, or in all directions if it's
+    // zero, narrowed to those toward target if set, at the given visibility.
+    fn seed_ranges(&mut self, dir: Point, target: Option<Point>, visibility: i32) {
+        if dir == Point::default() {
+            for transform in &TRANSFORMS {
+                let (mut min, mut max) = (Slope::new(-1, 1), Slope::new(1, 1));
+
+                // Skip this quadrant if the target outside it; else, filter.
+                if let Some(target) = target {
+                    let Transform([[a00, a01], [a10, a11]]) = *transform;
+                    let inverse = Transform([[a00, -a01], [-a10, a11]]);
+
+                    let Point(x, y) = inverse * target;
+                    if x == 0 || x < y.abs() { continue; }
+                    min = std::cmp::max(min, Slope::new(2 * y - 1, 2 * x));
+                    max = std::cmp::min(max, Slope::new(2 * y + 1, 2 * x));
+                }
+
+                // If the range is still non-empty, scan it.
+                if max <= min { continue; }
+                self.prev.items.push(SlopeRange { min, max, transform, visibility });
+            }
+        } else {
+            self.seed_cone(dir, ROT_LEFT_, ROT_RIGHT, target, visibility);
+        }
+    }
+
+    // Seeds the cone between dir rotated by left and by right, which must be
+    // rotations by at most 90 degrees to either side.
+    fn seed_cone(&mut self, dir: Point, left: Transform, right: Transform,
+                 target: Option<Point>, visibility: i32) {
+        for transform in &TRANSFORMS {
+            // Use the inverse to map dir into the right 90-degree quadrant.
+            let Transform([[a00, a01], [a10, a11]]) = *transform;
+            let inverse = Transform([[a00, -a01], [-a10, a11]]);
+            let Point(x, y) = inverse * dir;
+            let Point(lx, ly) = left * Point(x, y);
+            let Point(rx, ry) = right * Point(x, y);
+            debug_assert!(x != 0 || y != 0);
+
+            // Casework to figure out how the dir constrains slope ranges.
+            // Here, we rely on the fact that the window is <= 180 degrees.
+            let (mut min, mut max) = (Slope::new(-1, 1), Slope::new(1, 1));
+            if x < 0 {
+                if y == 0 { continue; }
+                if y > 0 {
+                    if rx <= 0 { continue; }
+                    min = std::cmp::max(min, Slope::new(ry, rx));
+                } else {
+                    if lx <= 0 { continue; }
+                    max = std::cmp::min(max, Slope::new(ly, lx));
+                }
+            } else {
+                if lx > 0 { max = std::cmp::min(max, Slope::new(ly, lx)); }
+                if rx > 0 { min = std::cmp::max(min, Slope::new(ry, rx)); }
+            }
+
+            // Skip this quadrant if the target outside it; else, filter.
+            if let Some(target) = target {
+                let Point(x, y) = inverse * target;
+                if x == 0 || x < y.abs() { continue; }
+                min = std::cmp::max(min, Slope::new(2 * y - 1, 2 * x));
+                max = std::cmp::min(max, Slope::new(2 * y + 1, 2 * x));
+            }
+
+            // If the range is still non-empty, scan it.
+            if max <= min { continue; }
+            self.prev.items.push(SlopeRange { min, max, transform, visibility });
+        }
+    }
+
+    fn execute<L: FnMut(Point, Point, &mut [i32]), T: FnMut(Point, &'static Transform)>(
+            &mut self, options: &CastOptions, eye: Point, limit: i32,
+            mut fetch: L, mut on_terminate: T) {
+        options.falloff.validate();
+        let radius = self.radius;
+        let center = Point(radius, radius);
+        self.nudge = options.boundary_r2_nudge(radius);
+        let r2 = disc_r2(radius) + self.nudge;
+        let origin = eye + options.projection_offset;
+
+        let push = |next: &mut SlopeRanges, s: SlopeRange| {
+            if let Some(x) = next.items.last_mut() {
+                let close = (x.visibility - s.visibility).abs() <= options.merge_tolerance;
+                if x.max == s.min && close && std::ptr::eq(x.transform, s.transform) {
+                    x.max = s.max;
+                    x.visibility = std::cmp::min(x.visibility, s.visibility);
+                    return;
+                }
+            }
+            if options.min_range_width > 0 {
+                let (min, max) = (s.min, s.max);
+                let num = (max.num * min.den - min.num * max.den) as i64;
+                let den = (max.den * min.den) as i64;
+                let narrow = 100 * radius as i64 * num < options.min_range_width as i64 * den;
+                let focused = || options.focus.is_some_and(|(focus, radius)| {
+                    let Transform([[a00, a01], [a10, a11]]) = *s.transform;
+                    let local = Transform([[a00, -a01], [-a10, a11]]) * (focus - eye);
+                    wedge_near_disc(min, max, local, radius)
+                });
+                if narrow && !focused() { return; }
+            }
+            next.items.push(s);
+        };
+
+        let limit_depth = limit;
+        while self.prev.depth <= limit && !self.prev.items.is_empty() {
+            let depth = self.prev.depth;
+
+            // Tiles at this depth are within the disc iff |width| <= reach.
+            let reach = metric_reach(self.metric, radius, r2, depth);
+
+            for range in &self.prev.items {
+                let mut prev_visibility = -1;
+                let SlopeRange { mut min, max, transform, visibility } = *range;
+                let start = div_floor(2 * min.num * depth + min.den, 2 * min.den);
+                let limit = div_ceil(2 * max.num * depth - max.den, 2 * max.den);
+
+                // Fetch opacities for the contiguous span of nearby tiles.
+                let step = *transform * Point(0, 1);
+                let (mut lo, mut hi) = (std::cmp::max(start, -reach), std::cmp::min(limit, reach));
+                if let Some(region) = options.valid_region {
+                    let base = *transform * Point(depth, 0) + eye;
+                    (lo, hi) = clip_span(region, base, step, lo, hi);
+                }
+                if lo <= hi {
+                    let first = *transform * Point(depth, lo) + eye;
+                    self.row.clear();
+                    self.row.resize((hi - lo + 1) as usize, 0);
+                    fetch(first, step, &mut self.row);
+                    if options.clear_first_ring && depth == 1 {
+                        self.row.iter_mut().filter(|x| **x < visibility).for_each(|x| *x = 0);
+                    }
+                    if options.see_over_height > 0 {
+                        let height = options.see_over_height;
+                        self.row.iter_mut().filter(|x| **x <= height).for_each(|x| *x = 0);
+                    }
+                    for &p in &options.transparent_override {
+                        let delta = p - first;
+                        let k = delta.dot(step) as i32;
+                        if Point(step.0 * k, step.1 * k) != delta { continue; }
+                        if let Some(x) = self.row.get_mut(k as usize) { *x = 0; }
+                    }
+                }
+
+                for width in start..=limit {
+                    let (x, y) = (depth, width);
+                    let nearby = lo <= width && width <= hi;
+                    let point = *transform * Point(x, y);
+
+                    let next_visibility = (|| {
+                        if !nearby { return -1; }
+                        let opacity = self.row[(width - lo) as usize];
+                        if opacity == 0 { return visibility; }
+                        if options.residual_on_equal && opacity == visibility { return 1; }
+                        options.falloff.attenuate(visibility, opacity, x, y)
+                    })();
+
+                    if next_visibility >= 0 {
+                        let entry = self.visibility.entry_mut(point + center).unwrap();
+                        if *entry < 0 { self.points_seen.push(point + origin); }
+                        *entry = std::cmp::max(*entry, next_visibility);
+                    }
+
+                    if next_visibility == 0 && options.wall_bleed > 0 && x < limit_depth {
+                        let opaque = self.row[(width - lo) as usize] > 0;
+                        let (bx, by) = (x + 1, div_floor(2 * y * (x + 1) + x, 2 * x));
+                        let point = *transform * Point(bx, by);
+                        let valid = options.valid_region.is_none_or(|r| in_region(r, point + eye));
+                        let near = by.abs() <= metric_reach(self.metric, radius, r2, bx);
+                        if opaque && valid && near {
+                            let entry = self.visibility.entry_mut(point + center).unwrap();
+                            if *entry < 0 { self.points_seen.push(point + origin); }
+                            *entry = std::cmp::max(*entry, options.wall_bleed);
+                        }
+                    }
+
+                    if prev_visibility != next_visibility && prev_visibility >= 0 {
+                        let slope = Slope::new(2 * width - 1, 2 * depth);
+                        if prev_visibility > 0 {
+                            let (max, visibility) = (slope, prev_visibility);
+                            let range = SlopeRange { min, max, transform, visibility };
+                            push(&mut self.next, range);
+                        }
+
+                        let visibility = options.diffraction;
+                        let diffract = |x: i32| visibility > 0 && x > visibility;
+                        if prev_visibility == 0 && diffract(next_visibility) {
+                            let min = std::cmp::max(min, Slope::new(width - 1, depth));
+                            let range = SlopeRange { min, max: slope, transform, visibility };
+                            push(&mut self.next, range);
+                        }
+                        if next_visibility == 0 && diffract(prev_visibility) {
+                            let max = std::cmp::min(max, Slope::new(width, depth));
+                            let range = SlopeRange { min: slope, max, transform, visibility };
+                            if range.min < range.max { push(&mut self.next, range); }
+                        }
+                        min = slope;
+                    }
+                    if next_visibility == 0 && prev_visibility != 0 {
+                        on_terminate(point + origin, transform);
+                    }
+                    prev_visibility = next_visibility;
+                }
+
+                if prev_visibility > 0 {
+                    let visibility = prev_visibility;
+                    let range = SlopeRange { min, max, transform, visibility };
+                    push(&mut self.next, range);
+                }
+            }
+
+            std::mem::swap(&mut self.prev, &mut self.next);
+            self.next.items.clear();
+            self.next.depth += 2;
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+// Combining results
+
+// The union of two visions' seen tiles in world coordinates, without repeats.
+pub fn union_seen(a: &Vision, b: &Vision) -> Vec<Point> {
+    let mut seen = HashSet::new();
+    let points = a.get_points_seen().iter().chain(b.get_points_seen());
+    points.filter(|&&p| seen.insert(p)).copied().collect()
+}
+
+pub fn max_visibility_at(a: &Vision, b: &Vision, p: Point) -> i32 {
+    std::cmp::max(a.get_visibility_at(p), b.get_visibility_at(p))
+}
+
+// The cells whose value differs between two same-sized light buffers, with
+// their new values, for incremental uploads.
+pub fn light_diff(old: &Matrix<i32>, new: &Matrix<i32>) -> Vec<(Point, i32)> {
+    assert!(old.size == new.size, "light_diff: {:?} != {:?}", old.size, new.size);
+    let cells = old.data.iter().zip(&new.data).enumerate();
+    cells.filter(|(_, (a, b))| a != b).map(|(i, (_, &b))| (new.index_to_point(i), b)).collect()
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+// Exploration
+
+// Clears the flags in an explored map, as kept by Vision::mark_explored, in
+// the inclusive world-space rectangle (min, max), or everywhere if region is
+// None, e.g. for amnesia or for unloading distant chunks.
+pub fn forget_explored(explored: &mut Matrix<bool>, region: Option<(Point, Point)>) {
+    let Some((min, max)) = region else { return explored.fill(false); };
+    for y in std::cmp::max(min.1, 0)..=std::cmp::min(max.1, explored.size.1 - 1) {
+        for x in std::cmp::max(min.0, 0)..=std::cmp::min(max.0, explored.size.0 - 1) {
+            explored.set(Point(x, y), false);
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+// Caching
+
+// Memoizes casts over a mostly-static level by eye and dir. When a tile's
+// opacity changes, call invalidate to drop the casts that saw it; casts that
+// didn't see it can't have been affected.
+pub struct CachedVision<F: OpacitySource> {
+    args: VisionArgs<F>,
+    cache: HashMap<(Point, Point), VisionResult>,
+    vision: Vision,
+}
+
+impl<F: OpacitySource> CachedVision<F> {
+    pub fn new(radius: i32, opacity_lookup: F, initial_visibility: i32) -> Self {
+        let (eye, dir, options) = (Point::default(), Point::default(), CastOptions::default());
+        let args = VisionArgs { eye, dir, opacity_lookup, initial_visibility, options };
+        Self { args, cache: HashMap::new(), vision: Vision::new(radius) }
+    }
+
+    pub fn fov(&mut self, eye: Point, dir: Point) -> &VisionResult {
+        let Self { args, cache, vision } = self;
+        cache.entry((eye, dir)).or_insert_with(|| {
+            (args.eye, args.dir) = (eye, dir);
+            vision.compute_result(args)
+        })
+    }
+
+    pub fn invalidate(&mut self, changed: Point) {
+        self.cache.retain(|_, x| x.get_visibility_at(changed) < 0);
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opacity::Layered;
+
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    const VISIBILITY_LOSS: i32 = VISIBILITY_LOSSES[2];
+
+    fn make_args<F: Fn(Point) -> i32>(eye: Point, dir: Point, opacity_lookup: F) -> VisionArgs<F> {
+        VisionArgs::new(eye, opacity_lookup).with_dir(dir)
+    }
+
+    fn run_fov(eye: Point, dir: Point, map: &Matrix<char>,
+               radius: i32, check_point_lookups: bool) -> Matrix<bool> {
+        // Wrapper around Vision to make it easier to test.
+        let opacity_lookup = |p: Point| -> i32 {
+            let c = if map.contains(p) { map.get(p) } else { '#' };
+            match c {
+                '#' => INITIAL_VISIBILITY,
+                ',' => VISIBILITY_LOSS,
+                _ => 0,
+            }
+        };
+        let args = make_args(eye, dir, opacity_lookup);
+
+        let mut vision = Vision::new(radius);
+        vision.compute(&args);
+
+        let mut result = Matrix::new(map.size, false);
+        for y in 0..map.size.1 {
+            for x in 0..map.size.0 {
+                let p = Point(x, y);
+                result.set(p, vision.get_visibility_at(p) >= 0);
+            }
+        }
+
+        // Check that point lookups are consistent with the full computation.
+        if check_point_lookups {
+            for y in 0..map.size.1 {
+                for x in 0..map.size.0 {
+                    let p = Point(x, y);
+                    assert!(result.get(p) == vision.can_see(&args, p));
+                }
+            }
+        }
+        result
+    }
+
+    fn test_fov(input: &[&str], expected: &[&str]) {
+        // Convert the input grid into a map.
+        let height = input.len();
+        let width = input[0].len();
+        let mut map = Matrix::new(Point(width as i32, height as i32), '#');
+        let mut eye = None;
+        let mut target = None;
+
+        for (y, row) in input.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let point = Point(x as i32, y as i32);
+                map.set(point, c);
+
+                if c == '@' {
+                    assert!(eye.is_none());
+                    eye = Some(point);
+                } else if c == 'X' {
+                    assert!(target.is_none());
+                    target = Some(point);
+                }
+            }
+        }
+
+        // Get the FOV result and compare it to the expected value.
+        let eye = eye.unwrap();
+        let dir = if let Some(x) = target { x - eye } else { Point::default() };
+        let visible = run_fov(eye, dir, &map, map.size.0 + map.size.1, true);
+        let result = show_fov(eye, &map, &visible);
+        if expected != result {
+            panic!("\nExpected:\n&{:#?}\n\nGot:\n&{:#?}", expected, result);
+        }
+    }
+
+    fn show_fov(eye: Point, map: &Matrix<char>, visible: &Matrix<bool>) -> Vec<String> {
+        let mut result = Vec::new();
+        for y in 0..map.size.1 {
+            let mut row = String::new();
+            for x in 0..map.size.0 {
+                let p = Point(x, y);
+                let (is_eye, is_visible) = (p == eye, visible.get(p));
+                let c = if is_eye { '@' } else if !is_visible { '%' } else { map.get(p) };
+                row.push(c);
+            }
+            result.push(row);
+        }
+        result
+    }
+
+    #[test]
+    fn test_empty() {
+        test_fov(&[
+            "@...",
+            "....",
+            "....",
+        ], &[
+            "@...",
+            "....",
+            "....",
+        ]);
+    }
+
+    #[test]
+    fn test_single_pillar() {
+        test_fov(&[
+            "@...",
+            ".#..",
+            "....",
+        ], &[
+            "@...",
+            ".#..",
+            "..%%",
+        ]);
+    }
+
+    #[test]
+    fn test_wall_with_gap() {
+        test_fov(&[
+            "@....",
+            ".....",
+            "..#..",
+            ".....",
+            "..#..",
+        ], &[
+            "@....",
+            ".....",
+            "..#..",
+            "...%.",
+            "..#.%",
+        ]);
+    }
+
+    #[test]
+    fn test_diagonal_wall() {
+        test_fov(&[
+            "@....",
+            ".....",
+            "..#..",
+            "...#.",
+            "....#",
+        ], &[
+            "@....",
+            ".....",
+            "..#..",
+            "...%.",
+            "....%",
+        ]);
+    }
+
+    #[test]
+    fn test_near_45() {
+        test_fov(&[
+            "@...",
+            "....",
+            "..#.",
+            "....",
+        ], &[
+            "@...",
+            "....",
+            "..#.",
+            "...%",
+        ]);
+    }
+
+    #[test]
+    fn test_gaps() {
+        test_fov(&[
+            "..........#",
+            "..........#",
+            "..........#",
+            "......#...#",
+            "..##..#...#",
+            "..........#",
+            "...@......#",
+            "......#...#",
+            "##....#...#",
+            "..........#",
+            "####..##..#",
+        ], &[
+            "%%%%%....%%",
+            "%%%%....%%%",
+            ".%%%...%%%%",
+            "..%%..#%%.#",
+            "..##..#...#",
+            "..........#",
+            "...@......#",
+            "......#...#",
+            "##....#%%%%",
+            "%.......%%%",
+            "####..##.%%",
+        ]);
+    }
+
+    #[test]
+    fn test_near_wall() {
+        test_fov(&[
+            "...............",
+            ".#############.",
+            ".#@..........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#############.",
+            "...............",
+        ], &[
+            "%%%%%%%%%%%%%%%",
+            "%#############%",
+            "%#@..........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#############%",
+            "%%%%%%%%%%%%%%%",
+        ]);
+    }
+
+
+    #[test]
+    fn test_large() {
+        test_fov(&[
+            "...............",
+            ".#############.",
+            ".#...........#.",
+            ".#...........#.",
+            ".#.......#...#.",
             ".#...........#.",
             ".#..#........#.",
             ".#.....@.....#.",
@@ -602,205 +2252,2026 @@ mod tests {
             ".#############.",
             "...............",
         ], &[
-            "%%%%%%%%%%%%%%%",
-            "%##########%##%",
-            "%#........%..#%",
-            "%#...........#%",
-            "%#.......#...#%",
-            "%%%..........#%",
-            "%#..#........#%",
-            "%#.....@.....#%",
-            "%#...........#%",
-            "%#...........#%",
-            "%#.......#...#%",
-            "%#....#......#%",
-            "%#........%..#%",
-            "%#.........%.#%",
-            "%#...%.....%%#%",
-            "%####%######%%%",
-            "%%%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%%%",
+            "%##########%##%",
+            "%#........%..#%",
+            "%#...........#%",
+            "%#.......#...#%",
+            "%%%..........#%",
+            "%#..#........#%",
+            "%#.....@.....#%",
+            "%#...........#%",
+            "%#...........#%",
+            "%#.......#...#%",
+            "%#....#......#%",
+            "%#........%..#%",
+            "%#.........%.#%",
+            "%#...%.....%%#%",
+            "%####%######%%%",
+            "%%%%%%%%%%%%%%%",
+        ]);
+    }
+
+    #[test]
+    fn test_field_of_grass() {
+        test_fov(&[
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,@,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+            ",,,,,,,,,,,,,,,",
+        ], &[
+            "%%%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%%%",
+            "%%%%%%,,,%%%%%%",
+            "%%%%%,,,,,%%%%%",
+            "%%%%,,,,,,,%%%%",
+            "%%%%,,,@,,,%%%%",
+            "%%%%,,,,,,,%%%%",
+            "%%%%%,,,,,%%%%%",
+            "%%%%%%,,,%%%%%%",
+            "%%%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%%%",
+        ]);
+    }
+
+    #[test]
+    fn test_semitransparent_walls() {
+        test_fov(&[
+            ".....,...,.,.,.",
+            ".....,.@.,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+            ".....,...,.,.,.",
+        ], &[
+            ".....,...,.,.,%",
+            ".....,.@.,.,.,%",
+            ".....,...,.,.,%",
+            ".....,...,.,%%%",
+            ".....,...,.,%%%",
+            ".....,...,.,%%%",
+            ".....,...,.,%%%",
+            "....%,...,%,%%%",
+            "....%,...,%%%%%",
+            "...%%,...,%%%%%",
+            "..%%%,...,%%%%%",
+            "..%%%,...,%%%%%",
+            ".%%%%,...,%%%%%",
+            "%%%%%,...,%%%%%",
+            "%%%%%,...,%%%%%",
+            "%%%%%,...,%%%%%",
+            "%%%%%,...,%%%%%",
+        ]);
+    }
+
+    #[test]
+    fn test_radius_zero() {
+        let eye = Point(2, 2);
+        let opacity_lookup = |_: Point| 0;
+        let args = make_args(eye, Point::default(), opacity_lookup);
+
+        let mut vision = Vision::new(0);
+        assert!(vision.can_see(&args, eye));
+        assert!(!vision.can_see(&args, eye + Point(1, 0)));
+        assert!(!vision.can_see(&args, eye + Point(1, 1)));
+
+        vision.compute(&args);
+        assert_eq!(vision.get_points_seen(), &[eye]);
+        assert_eq!(vision.get_visibility_at(eye), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(eye + Point(0, 1)), -1);
+    }
+
+    #[test]
+    fn test_directional_s() {
+        test_fov(&[
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            "......@......",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            "......X......",
+        ], &[
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%@%%%%%%",
+            "%%%%.....%%%%",
+            "%%.........%%",
+            "%...........%",
+            ".............",
+            ".............",
+            "......X......",
+        ]);
+    }
+
+    #[test]
+    fn test_directional_sw() {
+        test_fov(&[
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            "......@......",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            "X............",
+        ], &[
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            ".%%%%%%%%%%%%",
+            ".....%%%%%%%%",
+            "......@%%%%%%",
+            ".......%%%%%%",
+            "........%%%%%",
+            "........%%%%%",
+            "........%%%%%",
+            "........%%%%%",
+            "X........%%%%",
+        ]);
+    }
+
+    #[test]
+    fn test_directional_ssw() {
+        test_fov(&[
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            "......@......",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            ".............",
+            "...X.........",
+        ], &[
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "%%%%%%%%%%%%%",
+            "......@%%%%%%",
+            "........%%%%%",
+            "........%%%%%",
+            ".........%%%%",
+            "..........%%%",
+            "..........%%%",
+            "...X.......%%",
+        ]);
+    }
+
+    #[test]
+    fn test_row_opacity_matches_lookup() {
+        let (eye, map) = generate_fov_input();
+        let opacities = Matrix {
+            data: map.data.iter().map(|&c| match c {
+                '#' => INITIAL_VISIBILITY,
+                ',' => VISIBILITY_LOSS,
+                _ => 0,
+            }).collect(),
+            size: map.size,
+            default: INITIAL_VISIBILITY,
+        };
+        let opacity_lookup = |p: Point| opacities.get(p);
+        let row_fetch = |start: Point, step: Point, out: &mut [i32]| {
+            let end = Point(start.0 + step.0 * (out.len() as i32 - 1), start.1);
+            if step == Point(1, 0) && opacities.contains(start) && opacities.contains(end) {
+                let index = opacities.index(start).unwrap();
+                out.copy_from_slice(&opacities.data[index..index + out.len()]);
+                return;
+            }
+            let mut point = start;
+            for x in out {
+                *x = opacities.get(point);
+                point = point + step;
+            }
+        };
+
+        let initial_visibility = INITIAL_VISIBILITY;
+        let mut expected = Vision::new(15);
+        let mut actual = Vision::new(15);
+        for dir in [Point(0, 0), Point(1, 0), Point(-2, 3)] {
+            let args = make_args(eye, dir, opacity_lookup);
+            expected.compute(&args);
+            actual.compute_row_opacity(eye, dir, row_fetch, initial_visibility);
+
+            assert_eq!(expected.get_points_seen(), actual.get_points_seen());
+            for &p in expected.get_points_seen() {
+                assert_eq!(expected.get_visibility_at(p), actual.get_visibility_at(p));
+            }
+        }
+    }
+
+    #[test]
+    fn test_forget_region() {
+        let opacity_lookup = |_: Point| 0;
+        let (eye, dir) = (Point(4, 4), Point::default());
+        let args = make_args(eye, dir, opacity_lookup);
+
+        let mut vision = Vision::new(2);
+        vision.compute(&args);
+        let mut explored = Matrix::new(Point(9, 9), false);
+        vision.mark_explored(&mut explored);
+        assert_eq!(explored.data.iter().filter(|&&x| x).count(),
+                   vision.get_points_seen().len());
+
+        let (min, max) = (Point(2, 2), Point(4, 4));
+        forget_explored(&mut explored, Some((min, max)));
+        for &p in vision.get_points_seen() {
+            let inside = min.0 <= p.0 && p.0 <= max.0 && min.1 <= p.1 && p.1 <= max.1;
+            assert_eq!(explored.get(p), !inside);
+        }
+
+        forget_explored(&mut explored, None);
+        assert!(explored.data.iter().all(|&x| !x));
+    }
+
+    #[test]
+    fn test_explored_fraction() {
+        // A 4x4 map with a wall column at x = 0, of which we've seen x <= 1.
+        let mut passable = Matrix::new(Point(4, 4), true);
+        let mut explored = Matrix::new(Point(4, 4), false);
+        for y in 0..4 {
+            passable.set(Point(0, y), false);
+            explored.set(Point(0, y), true);
+            explored.set(Point(1, y), true);
+        }
+        assert_eq!(Vision::explored_fraction(&explored, &passable), 4.0 / 12.0);
+
+        explored.fill(true);
+        assert_eq!(Vision::explored_fraction(&explored, &passable), 1.0);
+        passable.fill(false);
+        assert_eq!(Vision::explored_fraction(&explored, &passable), 0.0);
+    }
+
+    #[test]
+    fn test_visible_aa_coverage() {
+        let mut map = Matrix::new(Point(6, 5), '.');
+        map.set(Point(2, 2), '#');
+        let opacity_lookup = |p: Point| if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 };
+        let (eye, dir) = (Point(0, 0), Point::default());
+        let args = make_args(eye, dir, opacity_lookup);
+
+        let vision = Vision::new(8);
+        let target = Point(3, 2);
+        assert!(vision.is_visible_aa(&args, target, 0.2));
+        assert!(!vision.is_visible_aa(&args, target, 0.5));
+        assert!(vision.is_visible_aa(&args, Point(2, 2), 1.0));
+        assert!(!vision.is_visible_aa(&args, Point(3, 3), 0.2));
+    }
+
+    #[test]
+    fn test_to_tcod_fov() {
+        let size = Point(7, 5);
+        let opacity_lookup = |p: Point| if p == Point(3, 2) { INITIAL_VISIBILITY } else { 0 };
+        let (eye, dir) = (Point(1, 2), Point::default());
+        let args = make_args(eye, dir, opacity_lookup);
+
+        let mut vision = Vision::new(4);
+        vision.compute(&args);
+        let fov = vision.to_tcod_fov(size);
+
+        assert_eq!(fov.size, size);
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                let p = Point(x, y);
+                assert_eq!(fov.get(p), vision.get_visibility_at(p) >= 0);
+            }
+        }
+        assert!(fov.get(Point(3, 2)));
+        assert!(!fov.get(Point(5, 2)));
+    }
+
+    #[test]
+    fn test_grass_corridor_dims_per_tile() {
+        // Each grass tile along the beam attenuates it again, so visibility
+        // falls off linearly with the number of tiles crossed.
+        let loss = VISIBILITY_LOSSES[6];
+        let opacity_lookup = |p: Point| if p.1 == 0 { loss } else { INITIAL_VISIBILITY };
+        let (eye, dir) = (Point(0, 0), Point::default());
+        let args = make_args(eye, dir, opacity_lookup);
+
+        let mut vision = Vision::new(12);
+        vision.compute(&args);
+
+        let visibility: Vec<_> = (0..10).map(|x| vision.get_visibility_at(Point(x, 0))).collect();
+        assert_eq!(visibility, [100, 85, 70, 55, 40, 25, 10, 0, -1, -1]);
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let opacity_lookup = |p: Point| if p == Point(1, 0) { INITIAL_VISIBILITY } else { 0 };
+        let (eye, dir) = (Point(0, 0), Point::default());
+        let args = make_args(eye, dir, opacity_lookup);
+
+        let buffer = Matrix::new(Point(7, 7), 17);
+        let points_seen = Vec::with_capacity(64);
+        let mut vision = Vision::from_parts(3, buffer, points_seen);
+        vision.compute(&args);
+
+        let mut expected = Vision::new(3);
+        expected.compute(&args);
+        assert_eq!(vision.get_points_seen(), expected.get_points_seen());
+        assert_eq!(vision.get_visibility_at(Point(2, 0)), -1);
+        assert_eq!(vision.get_visibility_at(Point(9, 9)), -1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_from_parts_wrong_size() {
+        Vision::from_parts(3, Matrix::new(Point(5, 5), -1), vec![]);
+    }
+
+    #[test]
+    fn test_cast_walls() {
+        // A room with interior [1, 7] x [1, 5] and walls on the border.
+        let size = Point(9, 7);
+        let inside = |p: Point| 0 < p.0 && p.0 < size.0 - 1 && 0 < p.1 && p.1 < size.1 - 1;
+        let opacity_lookup = |p: Point| if inside(p) { 0 } else { INITIAL_VISIBILITY };
+        let (eye, dir) = (Point(4, 3), Point::default());
+        let args = make_args(eye, dir, opacity_lookup);
+
+        let vision = Vision::new(10);
+        let walls = vision.cast_walls(&args, 32);
+        assert!(walls.iter().all(|&p| !inside(p)));
+        assert!(walls.iter().any(|p| p.0 == 0));
+        assert!(walls.iter().any(|p| p.0 == size.0 - 1));
+        assert!(walls.iter().any(|p| p.1 == 0));
+        assert!(walls.iter().any(|p| p.1 == size.1 - 1));
+        assert!(walls.iter().all(|p| p.0 >= 0 && p.0 < size.0 && p.1 >= 0 && p.1 < size.1));
+    }
+
+    #[test]
+    fn test_eye_at_map_corner() {
+        test_fov(&[
+            "@..",
+            "...",
+            "..#",
+        ], &[
+            "@..",
+            "...",
+            "..#",
         ]);
     }
 
     #[test]
-    fn test_field_of_grass() {
-        test_fov(&[
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,@,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-            ",,,,,,,,,,,,,,,",
-        ], &[
-            "%%%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%%%",
-            "%%%%%%,,,%%%%%%",
-            "%%%%%,,,,,%%%%%",
-            "%%%%,,,,,,,%%%%",
-            "%%%%,,,@,,,%%%%",
-            "%%%%,,,,,,,%%%%",
-            "%%%%%,,,,,%%%%%",
-            "%%%%%%,,,%%%%%%",
-            "%%%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%%%",
-        ]);
+    fn test_eye_on_opaque_tile() {
+        let queried = std::cell::Cell::new(false);
+        let eye = Point(0, 0);
+        let opacity_lookup = |p: Point| {
+            if p == eye { queried.set(true); }
+            if p.0 < 0 || p.1 < 0 || p == eye { INITIAL_VISIBILITY } else { 0 }
+        };
+        let dir = Point::default();
+        let args = make_args(eye, dir, opacity_lookup);
+
+        let mut vision = Vision::new(3);
+        vision.compute(&args);
+        assert!(!queried.get());
+        assert_eq!(vision.get_visibility_at(eye), INITIAL_VISIBILITY);
+        for p in [Point(1, 0), Point(0, 1), Point(1, 1), Point(3, 0)] {
+            assert_eq!(vision.get_visibility_at(p), INITIAL_VISIBILITY);
+        }
+        for p in [Point(-1, 0), Point(0, -1), Point(-1, -1)] {
+            assert_eq!(vision.get_visibility_at(p), 0);
+        }
+        assert_eq!(vision.get_visibility_at(Point(-2, 0)), -1);
+    }
+
+    #[test]
+    fn test_add_and_subtract_light() {
+        let size = Point(12, 8);
+        let opacity_lookup = |p: Point| if p.0 == 6 && p.1 != 4 { INITIAL_VISIBILITY } else { 0 };
+        let dir = Point::default();
+        let a = make_args(Point(2, 3), dir, opacity_lookup);
+        let b = make_args(Point(9, 5), dir, opacity_lookup);
+
+        let (mut va, mut vb) = (Vision::new(6), Vision::new(6));
+        va.compute(&a);
+        vb.compute(&b);
+
+        let mut light = Matrix::new(size, 0);
+        va.add_to_light(&mut light);
+        vb.add_to_light(&mut light);
+        va.subtract_from_light(&mut light);
+
+        let mut expected = Matrix::new(size, 0);
+        vb.add_to_light(&mut expected);
+        assert_eq!(light.data, expected.data);
+        assert!(light.data.iter().any(|&x| x > 0));
+    }
+
+    #[test]
+    fn test_accumulate_hdr_into() {
+        let mut light = Matrix::new(Point(12, 12), 0.0);
+        let mut vision = Vision::new(4);
+        for (eye, intensity) in [(Point(4, 4), 1.0), (Point(7, 4), 0.75)] {
+            vision.compute(&make_args(eye, Point::default(), |_| 0));
+            vision.accumulate_hdr_into(&mut light, intensity);
+        }
+        assert_eq!(light.get(Point(5, 4)), 1.75);
+        assert_eq!(light.get(Point(1, 4)), 1.0);
+        assert_eq!(light.get(Point(10, 4)), 0.75);
+        assert_eq!(light.get(Point(0, 0)), 0.0);
+        assert!(light.data.iter().any(|&x| x > 1.0));
+    }
+
+    #[test]
+    fn test_accumulate_into_capped() {
+        let cap = 3 * INITIAL_VISIBILITY;
+        let mut light = Matrix::new(Point(8, 8), 0);
+        let mut vision = Vision::new(5);
+        for i in 0..20 {
+            let eye = Point(i % 4 + 2, i / 4 + 2);
+            vision.compute(&make_args(eye, Point::default(), |_| 0));
+            vision.accumulate_into_capped(&mut light, cap);
+        }
+        assert!(light.data.iter().all(|&x| x <= cap));
+        assert_eq!(light.get(Point(4, 4)), cap);
+
+        // Near overflow, values saturate rather than wrapping.
+        light.fill(i32::MAX - 1);
+        vision.accumulate_into_capped(&mut light, i32::MAX);
+        assert_eq!(light.get(Point(4, 4)), i32::MAX);
+    }
+
+    #[test]
+    fn test_accessors() {
+        let opacity_lookup = |_: Point| 0;
+        let (eye, dir) = (Point(-7, 12), Point::default());
+        let args = make_args(eye, dir, opacity_lookup);
+
+        let mut vision = Vision::new(5);
+        assert_eq!(vision.radius(), 5);
+        vision.compute(&args);
+        assert_eq!(vision.eye(), eye);
+        assert_eq!(vision.offset(), Point(5, 5) - eye);
+
+        vision.can_see(&args, Point(0, 0));
+        assert_eq!(vision.eye(), eye);
+        assert_eq!(vision.eye(), eye);
+
+        // can_see re-centers the field on its own eye.
+        let other = make_args(Point(3, 4), dir, opacity_lookup);
+        vision.can_see(&other, Point(4, 4));
+        assert_eq!(vision.eye(), Point(3, 4));
+        assert_eq!(vision.offset(), Point(5, 5) - Point(3, 4));
+        assert_eq!(vision.radius(), 5);
+    }
+
+    #[test]
+    fn test_seen() {
+        let lookup = |p: Point| if p.0 == 2 { VISIBILITY_LOSS } else { 0 };
+        let mut vision = Vision::new(4);
+        vision.compute(&make_args(Point(-1, 3), Point::default(), lookup));
+        let seen: Vec<_> = vision.seen().collect();
+        assert_eq!(seen.len(), vision.get_points_seen().len());
+        for (&p, &(q, visibility)) in vision.get_points_seen().iter().zip(&seen) {
+            assert_eq!(p, q);
+            assert!(visibility >= 0);
+            assert_eq!(visibility, vision.get_visibility_at(p));
+        }
+        assert!(seen.contains(&(Point(2, 3), INITIAL_VISIBILITY - VISIBILITY_LOSS)));
+    }
+
+    #[test]
+    fn test_compute_tracking() {
+        let (eye, last_known) = (Point(0, 0), Point(10, 3));
+        let lookup = |_: Point| 0;
+        let mut vision = Vision::new(12);
+        let track = |vision: &mut Vision, margin: f64, options: &CastOptions| {
+            vision.compute_tracking(eye, last_known, margin, lookup, INITIAL_VISIBILITY, options);
+            vision.points_seen_sorted()
+        };
+
+        // With no margin, this matches the usual directional cast.
+        let tracked = track(&mut vision, 0.0, &CastOptions::default());
+        vision.compute(&make_args(eye, last_known - eye, lookup));
+        assert_eq!(tracked, vision.points_seen_sorted());
+        assert!(tracked.contains(&last_known));
+        assert!(!tracked.contains(&Point(1, 7)));
+
+        // (1, 7) is ~65 degrees off the heading, within the widened cone.
+        let widened = track(&mut vision, 0.2, &CastOptions::default());
+        assert!(widened.contains(&last_known) && widened.contains(&Point(1, 7)));
+        assert!(tracked.iter().all(|p| widened.contains(p)));
+        assert!(!widened.contains(&Point(-5, 0)));
+
+        // Options apply to the cast, here shifting it by a projection.
+        let projection_offset = Point(0, 20);
+        let options = CastOptions { projection_offset, ..Default::default() };
+        let projected = track(&mut vision, 0.2, &options);
+        assert_eq!(projected, widened.iter().map(|&p| p + projection_offset).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_am_i_seen() {
+        let me = Point(0, 0);
+        let lookup = |p: Point| if p == Point(0, 3) { INITIAL_VISIBILITY } else { 0 };
+        let half_angle = std::f64::consts::FRAC_PI_4;
+        let mut vision = Vision::new(8);
+
+        // One guard faces the player and one faces away; only the first sees.
+        let toward = (Point(5, 1), Point(-1, 0));
+        let away = (Point(-5, 1), Point(-1, 0));
+        let options = CastOptions::default();
+        let seen = |vision: &mut Vision, viewers: &[(Point, Point)]| {
+            vision.am_i_seen(viewers, me, half_angle, lookup, INITIAL_VISIBILITY, &options)
+        };
+        assert!(seen(&mut vision, &[toward]));
+        assert!(!seen(&mut vision, &[away]));
+        assert!(seen(&mut vision, &[away, toward]));
+        assert!(!seen(&mut vision, &[]));
+
+        // A guard facing the player is still blocked by walls and range.
+        assert!(!seen(&mut vision, &[(Point(0, 6), Point(0, -1))]));
+        assert!(!seen(&mut vision, &[(Point(12, 0), Point(-1, 0))]));
+        assert!(seen(&mut vision, &[(Point(0, -6), Point::default())]));
+
+        // Options apply to each viewer's cast, here letting it see past a wall.
+        let options = CastOptions { transparent_override: vec![Point(0, 3)], ..Default::default() };
+        let viewers = [(Point(0, 6), Point(0, -1))];
+        assert!(vision.am_i_seen(&viewers, me, half_angle, lookup, INITIAL_VISIBILITY, &options));
+    }
+
+    #[test]
+    fn test_compute_blended_opacity() {
+        let (eye, dir) = (Point(0, 0), Point(1, 0));
+        let smoke = |p: Point| if p.0 == 2 { 60 } else { 0 };
+        let clear = |p: Point| if p == Point(3, 3) { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(6);
+        let field = |vision: &Vision| {
+            let mut result: Vec<_> = vision.seen().collect();
+            result.sort_unstable_by_key(|&(p, _)| (p.1, p.0));
+            result
+        };
+
+        // The endpoints match casts with each opacity alone.
+        let mut args = make_args(eye, dir, smoke);
+        for (t, lookup) in [(0.0, &smoke as &dyn Fn(Point) -> i32), (1.0, &clear)] {
+            vision.compute_blended_opacity(&args, clear, t);
+            let blended = field(&vision);
+            vision.compute(&make_args(eye, dir, lookup));
+            assert_eq!(blended, field(&vision));
+        }
+
+        // Halfway, the smoke is thinner, so more light gets through it.
+        vision.compute(&args);
+        let thick = vision.get_visibility_at(Point(4, 0));
+        vision.compute_blended_opacity(&args, clear, 0.5);
+        assert!(vision.get_visibility_at(Point(4, 0)) > thick);
+
+        // Options apply to the cast, here changing how the smoke attenuates.
+        args.options.falloff = Falloff::Multiplicative(2.0 * INITIAL_VISIBILITY as f32);
+        vision.compute_blended_opacity(&args, clear, 0.0);
+        let blended = field(&vision);
+        vision.compute(&args);
+        assert_eq!(blended, field(&vision));
+        assert!(vision.get_visibility_at(Point(4, 0)) > thick);
+    }
+
+    #[test]
+    fn test_visibility_at() {
+        let lookup = |p: Point| if p == Point(2, 0) { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(4);
+        vision.compute(&make_args(Point(0, 0), Point::default(), lookup));
+        assert_eq!(vision.visibility_at(Point(1, 0)), Some(INITIAL_VISIBILITY));
+        assert_eq!(vision.visibility_at(Point(2, 0)), Some(0));
+        assert_eq!(vision.visibility_at(Point(3, 0)), None);
+        assert_eq!(vision.visibility_at(Point(100, 0)), None);
+        assert!(vision.can_see_point(Point(2, 0)) && !vision.can_see_point(Point(3, 0)));
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), -1);
+    }
+
+    #[test]
+    fn test_resize() {
+        let lookup = |p: Point| if p == Point(2, 0) { INITIAL_VISIBILITY } else { 0 };
+        let args = make_args(Point(3, -2), Point::default(), lookup);
+        let mut vision = Vision::new(8);
+        vision.compute(&args);
+        let buffer = vision.visibility.data.as_ptr();
+
+        // Shrinking and growing back within the allocation doesn't reallocate.
+        for radius in [3, 0, 5, 8] {
+            vision.resize(radius);
+            assert_eq!(vision.radius(), radius);
+            assert_eq!(vision.eye(), args.eye);
+            assert!(vision.get_points_seen().is_empty());
+            assert_eq!(vision.visibility.data.as_ptr(), buffer);
+
+            let mut expected = Vision::new(radius);
+            vision.compute(&args);
+            expected.compute(&args);
+            assert_eq!(vision.get_points_seen(), expected.get_points_seen());
+            assert_eq!(vision.visibility.data, expected.visibility.data);
+        }
+
+        vision.resize(12);
+        vision.compute(&args);
+        assert_eq!(vision.visibility.size, Point(25, 25));
+        assert_eq!(vision.visibility_at(args.eye + Point(12, 0)), Some(INITIAL_VISIBILITY));
+    }
+
+    #[test]
+    fn test_to_sdf() {
+        let mut vision = Vision::new(6);
+        vision.compute(&make_args(Point(10, 10), Point::default(), |_: Point| 0));
+        let sdf = vision.to_sdf((Point(0, 0), Point(20, 20)));
+        assert_eq!(sdf.size, Point(21, 21));
+
+        // The eye is deep inside; tiles on either side of the edge are near 0.
+        assert_eq!(sdf.get(Point(10, 10)), -7);
+        assert_eq!(sdf.get(Point(16, 10)), -1);
+        assert_eq!(sdf.get(Point(17, 10)), 1);
+        assert_eq!(sdf.get(Point(20, 10)), 4);
+        assert!(sdf.data.iter().all(|&x| x != 0));
+        for y in 0..21 {
+            for x in 0..21 {
+                let p = Point(x, y);
+                assert_eq!(sdf.get(p) < 0, vision.can_see_point(p));
+            }
+        }
+
+        // Bounds that are all seen or all unseen have no edge to measure to.
+        let sdf = vision.to_sdf((Point(8, 8), Point(12, 12)));
+        assert!(sdf.data.iter().all(|&x| x == -SDF_UNBOUNDED));
+        let sdf = vision.to_sdf((Point(30, 30), Point(33, 34)));
+        assert_eq!(sdf.size, Point(4, 5));
+        assert!(sdf.data.iter().all(|&x| x == SDF_UNBOUNDED));
+    }
+
+    #[test]
+    fn test_merge_tolerance() {
+        // Grass whose opacity varies slightly from tile to tile.
+        let lookup = |p: Point| VISIBILITY_LOSSES[4] + (7 * p.0 + 13 * p.1).rem_euclid(5);
+        let mut args = make_args(Point(0, 0), Point::default(), lookup);
+
+        // Runs the cast a row at a time to count the beams in flight.
+        let cast = |vision: &mut Vision, args: &VisionArgs<_>| {
+            vision.clear(args.eye, args.initial_visibility);
+            vision.seed_ranges(args.dir, None, args.initial_visibility);
+            let mut total = 0;
+            for depth in 1..=vision.radius {
+                let fetch = lookup_span(&args.opacity_lookup);
+                vision.execute(&args.options, args.eye, depth, fetch, |_, _| {});
+                total += vision.prev.items.len();
+            }
+            total
+        };
+        let (mut exact, mut merged) = (Vision::new(12), Vision::new(12));
+        let exact_beams = cast(&mut exact, &args);
+        args.options.merge_tolerance = 5;
+        let merged_beams = cast(&mut merged, &args);
+        assert!(merged_beams < exact_beams);
+
+        // Merging only ever dims tiles, never reveals new ones.
+        for &p in merged.get_points_seen() {
+            assert!(merged.get_visibility_at(p) <= exact.get_visibility_at(p));
+        }
+    }
+
+    #[test]
+    fn test_target_rule() {
+        // A wall at (4, 2) shadows slopes in [3/8, 5/8] beyond it.
+        let wall = |p: Point| if p == Point(4, 2) { INITIAL_VISIBILITY } else { 0 };
+        let mut args = make_args(Point(0, 0), Point::default(), wall);
+        let mut vision = Vision::new(10);
+        let cases = [
+            (Point(3, 0), [true, true, true]),
+            (Point(-5, 5), [true, true, true]),
+            (Point(6, 4), [true, true, false]),
+            (Point(7, 4), [true, false, false]),
+            (Point(8, 4), [false, false, false]),
+        ];
+        let rules = [TargetRule::AnyCorner, TargetRule::Center, TargetRule::AllCorners];
+        for (target, expected) in cases {
+            for (rule, expected) in rules.into_iter().zip(expected) {
+                args.options.target_rule = rule;
+                assert_eq!(vision.can_see(&args, target), expected, "{:?} {:?}", target, rule);
+            }
+        }
+
+        // AnyCorner agrees with compute.
+        args.options.target_rule = TargetRule::AnyCorner;
+        let mut full = Vision::new(10);
+        full.compute(&args);
+        assert!(full.get_visibility_at(Point(7, 4)) >= 0);
+        assert!(full.get_visibility_at(Point(8, 4)) < 0);
+    }
+
+    #[test]
+    fn test_coverage_by_octant() {
+        let mut vision = Vision::new(6);
+        vision.compute(&make_args(Point(2, 3), Point::default(), |_: Point| 0));
+        let coverage = vision.coverage_by_octant();
+        assert!((0..8).all(|i| coverage[i] == coverage[(i + 2) % 8]));
+        assert_eq!(coverage.iter().sum::<usize>(), vision.get_points_seen().len() - 1);
+
+        vision.compute(&make_args(Point(2, 3), Point(1, 0), |_: Point| 0));
+        let coverage = vision.coverage_by_octant();
+        let forward = coverage[0] + coverage[7];
+        let backward = coverage[3] + coverage[4];
+        assert!(forward > 0 && backward < forward);
+        assert_eq!(coverage.iter().sum::<usize>(), vision.get_points_seen().len() - 1);
+    }
+
+    #[test]
+    fn test_cached_vision() {
+        let lookups = std::cell::Cell::new(0);
+        let lookup = |p: Point| {
+            lookups.set(lookups.get() + 1);
+            if p == Point(3, 0) { INITIAL_VISIBILITY } else { 0 }
+        };
+        let mut cached = CachedVision::new(6, lookup, INITIAL_VISIBILITY);
+        let (eye, dir) = (Point(0, 0), Point::default());
+
+        let seen = cached.fov(eye, dir).get_points_seen().len();
+        let count = lookups.get();
+        assert!(count > 0);
+        assert_eq!(cached.fov(eye, dir).get_points_seen().len(), seen);
+        assert_eq!(lookups.get(), count);
+
+        // A tile outside this FOV doesn't invalidate it; one inside does.
+        cached.fov(Point(20, 0), dir);
+        assert_eq!(cached.len(), 2);
+        cached.invalidate(Point(5, 0));
+        assert_eq!(cached.len(), 2);
+        cached.invalidate(Point(1, 1));
+        assert_eq!(cached.len(), 1);
+
+        let count = lookups.get();
+        assert_eq!(cached.fov(eye, dir).get_points_seen().len(), seen);
+        assert!(lookups.get() > count);
+    }
+
+    #[test]
+    fn test_sightlines() {
+        // A corridor three tiles wide running east from the eye.
+        let corridor = |p: Point| if p.0 >= 0 && p.1.abs() <= 1 { 0 } else { INITIAL_VISIBILITY };
+        let mut vision = Vision::new(8);
+        vision.compute(&make_args(Point(2, 3), Point::default(), |p| corridor(p - Point(2, 3))));
+        assert_eq!(vision.sightlines(), vec![(Point(10, 2), Point(10, 4))]);
+
+        // Capping the corridor short of the radius blocks every sightline.
+        let capped = |p: Point| if p.0 == 6 { INITIAL_VISIBILITY } else { corridor(p) };
+        vision.compute(&make_args(Point(0, 0), Point::default(), capped));
+        assert!(vision.get_visibility_at(Point(6, 0)) >= 0);
+        assert!(vision.sightlines().is_empty());
+    }
+
+    #[test]
+    fn test_compute_with_grass() {
+        let wall = |p: Point| if p == Point(0, 2) { INITIAL_VISIBILITY } else { 0 };
+        let grass = |p: Point| match p {
+            Point(1, 0) => 10,
+            Point(2, 0) => 15,
+            Point(x, _) if x < 0 => VISIBILITY_LOSS,
+            _ => 0,
+        };
+        let args = make_args(Point(0, 0), Point::default(), wall);
+        let mut density = Matrix::new(Point(16, 16), -1);
+        let mut vision = Vision::new(6);
+        vision.compute_with_grass(&args, grass, &mut density);
+
+        // The tile behind both grass tiles sums their opacities.
+        assert_eq!(density.get(Point(3, 0)), 25);
+        assert_eq!(density.get(Point(0, 1)), 0);
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), INITIAL_VISIBILITY);
+
+        // Walls still block, but even dense grass doesn't.
+        assert_eq!(vision.get_visibility_at(Point(0, 2)), 0);
+        assert_eq!(vision.get_visibility_at(Point(0, 3)), -1);
+        assert_eq!(vision.get_visibility_at(Point(-6, 0)), INITIAL_VISIBILITY);
+        assert_eq!(density.get(Point(0, 2)), -1);
+    }
+
+    #[test]
+    fn test_facing_toward() {
+        let (eye, lookup) = (Point(2, 3), |_: Point| 0);
+        let args = make_args(Point::default(), Point::default(), lookup);
+        let args = args.facing_toward(eye, Point(2, 1000));
+        assert_eq!((args.eye, args.dir), (eye, Point(0, 1)));
+
+        let mut vision = Vision::new(5);
+        vision.compute(&args);
+        assert!(vision.get_visibility_at(eye + Point(0, 5)) >= 0);
+        assert!(vision.get_visibility_at(eye + Point(5, 0)) < 0);
+        assert!(vision.get_visibility_at(eye + Point(0, -5)) < 0);
+
+        let args = args.facing_toward(eye, Point(-6, 15));
+        assert_eq!(args.dir, Point(-2, 3));
+        let args = args.facing_toward(eye, eye);
+        assert_eq!(args.dir, Point::default());
+        let args = args.facing_toward(Point(i32::MIN, 0), Point(i32::MAX, -7));
+        assert_eq!(args.dir, Point(1, 0));
+    }
+
+    #[test]
+    fn test_large_radius_disc() {
+        // Here, radius * radius + radius overflows an i32.
+        let radius: i32 = 50_000;
+        assert!(radius.checked_mul(radius).and_then(|x| x.checked_add(radius)).is_none());
+        let r2 = disc_r2(radius);
+        assert_eq!(r2, 2_500_050_000);
+        assert_eq!(disc_reach(r2, 0), radius);
+        assert_eq!(disc_reach(r2, radius), 223);
+        assert_eq!(disc_reach(r2, radius + 1), -1);
+        assert_eq!(disc_reach(r2, 30_000), 40_000);
+
+        // The boundary matches the exact test against r^2 + r.
+        for depth in [1, 12_345, 49_999] {
+            let reach = disc_reach(r2, depth) as i64;
+            let (d, r) = (depth as i64, radius as i64);
+            assert!(d * d + reach * reach <= r * r + r);
+            assert!(d * d + (reach + 1) * (reach + 1) > r * r + r);
+        }
+    }
+
+    #[test]
+    fn test_explain() {
+        let lookup = |p: Point| match p {
+            Point(2, 0) => INITIAL_VISIBILITY,
+            Point(0, -1) => VISIBILITY_LOSS,
+            _ => 0,
+        };
+        let args = make_args(Point(0, 0), Point::default(), lookup);
+        let mut vision = Vision::new(6);
+
+        let explanation = vision.explain(&args, Point(4, 0));
+        assert!(!explanation.visible && !vision.can_see(&args, Point(4, 0)));
+        assert_eq!(explanation.visibility, -1);
+        assert_eq!(explanation.blockers, vec![Point(2, 0)]);
+        assert_eq!(explanation.quadrant, 0);
+
+        let explanation = vision.explain(&args, Point(0, -3));
+        assert!(explanation.visible);
+        assert_eq!(explanation.visibility, INITIAL_VISIBILITY - VISIBILITY_LOSS);
+        assert_eq!(explanation.blockers, vec![Point(0, -1)]);
+        assert_eq!(TRANSFORMS[explanation.quadrant] * Point(3, 0), Point(0, -3));
+
+        let explanation = vision.explain(&args, Point(7, 0));
+        assert!(!explanation.visible && explanation.blockers.is_empty());
+    }
+
+    #[test]
+    fn test_clear_first_ring() {
+        let grass = |p: Point| if p == Point(0, 1) { INITIAL_VISIBILITY } else { VISIBILITY_LOSS };
+        let mut args = make_args(Point(0, 0), Point::default(), grass);
+        let (mut plain, mut parted) = (Vision::new(4), Vision::new(4));
+        plain.compute(&args);
+        args.options.clear_first_ring = true;
+        parted.compute(&args);
+
+        for x in -1..=1 {
+            for y in -1..=1 {
+                let p = Point(x, y);
+                if p == Point(0, 0) || p == Point(0, 1) { continue; }
+                assert_eq!(parted.get_visibility_at(p), INITIAL_VISIBILITY);
+                assert!(plain.get_visibility_at(p) < INITIAL_VISIBILITY);
+            }
+        }
+        assert_eq!(parted.get_visibility_at(Point(0, 1)), 0);
+        assert_eq!(parted.get_visibility_at(Point(2, 0)), INITIAL_VISIBILITY - VISIBILITY_LOSS);
+    }
+
+    #[test]
+    fn test_swept_fov() {
+        // An east-west corridor with a side room off of x = 3.
+        let open = |p: Point| p.1 == 0 || (p.0 == 3 && p.1 < 0 && p.1 > -4);
+        let lookup = |p: Point| if open(p) { 0 } else { INITIAL_VISIBILITY };
+        let path = [Point(0, 0), Point(1, 0), Point(2, 0), Point(3, 0)];
+        let mut vision = Vision::new(5);
+        let options = CastOptions::default();
+        let swept = vision.swept_fov(&path, Point::default(), lookup, INITIAL_VISIBILITY, &options);
+
+        let mut expected = HashSet::new();
+        for &eye in &path {
+            vision.compute(&make_args(eye, Point::default(), lookup));
+            expected.extend(vision.get_points_seen().iter().copied());
+        }
+        assert_eq!(swept.len(), expected.len());
+        assert_eq!(swept.iter().copied().collect::<HashSet<_>>(), expected);
+        assert!(swept.contains(&Point(3, -3)));
+
+        // Options apply to every cast along the path.
+        let valid_region = Some((Point(-5, -1), Point(10, 1)));
+        let options = CastOptions { valid_region, ..Default::default() };
+        let swept = vision.swept_fov(&path, Point::default(), lookup, INITIAL_VISIBILITY, &options);
+        assert!(swept.contains(&Point(3, -1)) && !swept.contains(&Point(3, -2)));
+    }
+
+    #[test]
+    fn test_boundary_epsilon() {
+        // (3, 2) is just outside radius 3, while (4, 2) is right on radius 4.
+        let mut args = make_args(Point(0, 0), Point::default(), |_: Point| 0);
+        let mut check = |radius: i32, epsilon: i32, target: Point| {
+            args.options.boundary_epsilon = epsilon;
+            let mut vision = Vision::new(radius);
+            vision.compute(&args);
+            let seen = vision.get_visibility_at(target) >= 0;
+            assert_eq!(vision.can_see(&args, target), seen);
+            seen
+        };
+        assert!(!check(3, 0, Point(3, 2)));
+        assert!(check(3, 1, Point(3, 2)));
+        assert!(check(4, 0, Point(4, 2)));
+        assert!(!check(4, -1, Point(4, 2)));
+
+        // Large nudges are clamped to keep the disc in bounds.
+        assert!(check(4, 1000, Point(4, 2)));
+        assert!(!check(4, 1000, Point(4, 3)));
+        assert!(!check(4, 1000, Point(5, 0)));
+
+        // The shadow and the ray-traced checks apply the same nudge, so tiles
+        // nudged out of the field aren't reported as blind spots.
+        let mut vision = Vision::new(4);
+        assert!(vision.is_visible_aa(&args, Point(4, 2), 1.0));
+        args.options.boundary_epsilon = -1;
+        vision.compute(&args);
+        assert_eq!(vision.iter_shadow().count(), 0);
+        assert!(!vision.is_visible_aa(&args, Point(4, 2), 0.2));
+        assert!(vision.is_visible_aa(&args, Point(4, 1), 1.0));
+    }
+
+    #[test]
+    fn test_iter_shadow() {
+        // The map from test_single_pillar, whose pillar shadows (2, 2).
+        let eye = Point(3, 5);
+        let lookup = |p: Point| if p - eye == Point(1, 1) { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(3);
+        vision.compute(&make_args(eye, Point::default(), lookup));
+
+        let shadow: Vec<_> = vision.iter_shadow().map(|p| p - eye).collect();
+        assert!(shadow.contains(&Point(2, 2)));
+        assert!(!shadow.contains(&Point(1, 1)) && !shadow.contains(&Point(3, 1)));
+        assert!(shadow.iter().all(|&p| vision.get_visibility_at(p + eye) < 0));
+
+        let disc = (-3..=3).flat_map(|y| (-3..=3).map(move |x| Point(x, y)));
+        let disc = disc.filter(|p| p.len_l2_squared() <= 12).count();
+        assert_eq!(shadow.len() + vision.get_points_seen().len(), disc);
+    }
+
+    #[test]
+    fn test_focus() {
+        // The pillar grid from test_min_range_width.
+        let lookup = |p: Point| {
+            let Point(x, y) = p;
+            let pillar = x.abs() > 2 && y.abs() > 2 && x % 3 == 0 && y % 3 == 0;
+            if pillar { INITIAL_VISIBILITY } else { 0 }
+        };
+        let mut args = make_args(Point(0, 0), Point::default(), lookup);
+        let (mut exact, mut rough) = (Vision::new(20), Vision::new(20));
+        let mut focused = Vision::new(20);
+        exact.compute(&args);
+        args.options.min_range_width = 100;
+        rough.compute(&args);
+        let (center, radius) = (Point(-13, 8), 4);
+        args.options.focus = Some((center, radius));
+        focused.compute(&args);
+
+        // The focus matches the exact cast, though the rough one misses tiles.
+        let in_focus = |p: &&Point| p.dist2(center) <= (radius * radius) as i64;
+        let focus = |v: &Vision| v.get_points_seen().iter().filter(in_focus).count();
+        assert!(focus(&exact) > 2 * focus(&rough));
+        assert_eq!(focus(&focused), focus(&exact));
+
+        // The periphery is still approximate, but no worse than without focus.
+        let (exact_seen, focused_seen) = (exact.get_points_seen(), focused.get_points_seen());
+        assert!(focused_seen.iter().all(|&p| exact.get_visibility_at(p) >= 0));
+        assert!(focused_seen.len() < exact_seen.len());
+        assert!(focused_seen.len() >= rough.get_points_seen().len());
+    }
+
+    #[test]
+    fn test_vision_builder() {
+        let error = VisionBuilder::new().radius(-1).build().err();
+        assert_eq!(error, Some(VisionError::NegativeRadius(-1)));
+
+        // Each metric bounds the field in an open map, and can_see agrees.
+        let args = make_args(Point(2, 3), Point::default(), |_: Point| 0);
+        let metrics = [RadiusMetric::Euclidean, RadiusMetric::Chebyshev,
+                       RadiusMetric::Taxicab, RadiusMetric::Nethack];
+        for metric in metrics {
+            let mut vision = VisionBuilder::new().radius(6).metric(metric).build().unwrap();
+            vision.compute(&args);
+            let deltas = (-7..=7).flat_map(|y| (-7..=7).map(move |x| Point(x, y)));
+            let within = |d: Point| d.len_l1() <= 6 && metric.within(d, 6);
+            let expected: Vec<_> = deltas.map(|d| (d, within(d))).collect();
+            for &(delta, within) in &expected {
+                assert_eq!(vision.get_visibility_at(delta + args.eye) >= 0, within);
+            }
+            for &(delta, within) in &expected {
+                assert_eq!(vision.can_see(&args, delta + args.eye), within);
+            }
+        }
+
+        // Args from the Vision start with its initial visibility, as do the
+        // beams that leave the eye, and validation catches negative values.
+        let error = VisionBuilder::new().initial_visibility(-5).build().err();
+        assert_eq!(error, Some(VisionError::NegativeInitialVisibility(-5)));
+        let mut vision = VisionBuilder::new().radius(3).initial_visibility(50).build().unwrap();
+        let args = vision.args(Point(0, 0), |_: Point| VISIBILITY_LOSS);
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(0, 0)), 50);
+        assert_eq!(vision.get_visibility_at(Point(1, 0)), 50 - VISIBILITY_LOSS);
+        assert_eq!(vision.get_visibility_at(Point(2, 0)), 0);
+
+        // Explicit args override it.
+        vision.compute(&args.with_initial_visibility(80));
+        assert_eq!(vision.get_visibility_at(Point(0, 0)), 80);
+        assert_eq!(vision.get_visibility_at(Point(1, 0)), 80 - VISIBILITY_LOSS);
+
+        // The ray-traced checks also respect the metric.
+        let args = make_args(Point(0, 0), Point::default(), |_: Point| 0);
+        let square = VisionBuilder::new().radius(4).metric(RadiusMetric::Chebyshev).build();
+        assert!(square.unwrap().is_visible_aa(&args, Point(4, 4), 1.0));
+        assert!(!Vision::new(4).is_visible_aa(&args, Point(4, 4), 0.2));
+
+        let ring = |p: Point| if p.len_l1() == 4 { INITIAL_VISIBILITY } else { 0 };
+        let args = make_args(Point(0, 0), Point::default(), ring);
+        let walls = Vision::new(4).cast_walls(&args, 64);
+        assert!(walls.iter().any(|p| p.len_taxicab() > 4));
+        let diamond = VisionBuilder::new().radius(4).metric(RadiusMetric::Taxicab).build();
+        let walls = diamond.unwrap().cast_walls(&args, 64);
+        assert!(!walls.is_empty() && walls.iter().all(|p| p.len_taxicab() <= 4));
+    }
+
+    #[test]
+    fn test_compute_with_seed() {
+        let (eye, map) = generate_fov_input();
+        let lookup = |p: Point| if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 };
+        let (mut seeded, mut vision) = (Vision::new(12), Vision::new(12));
+        for dir in [Point::default(), Point(1, 0), Point(-3, 7), Point(0, -1)] {
+            let template = seeded.precompute_seed(dir);
+            assert_eq!(template.dir(), dir);
+            for eye in [eye, eye + Point(-4, 2), eye + Point(5, 5)] {
+                let mut args = make_args(eye, dir, lookup);
+                args.initial_visibility = 60;
+                args.options.wall_bleed = 5;
+                seeded.compute_with_seed(&args, &template);
+                vision.compute(&args);
+                assert_eq!(seeded.get_points_seen(), vision.get_points_seen());
+                assert_eq!(seeded.visibility.data, vision.visibility.data);
+            }
+        }
+
+        // Precomputing a template leaves the last cast's results in place,
+        // and the template works for another Vision.
+        let seen = seeded.points_seen_sorted();
+        let template = seeded.precompute_seed(Point(1, 0));
+        assert_eq!(seeded.points_seen_sorted(), seen);
+        let args = make_args(eye, Point(1, 0), lookup);
+        let mut other = Vision::new(7);
+        other.compute_with_seed(&args, &template);
+        vision.resize(7);
+        vision.compute(&args);
+        assert_eq!(other.points_seen_sorted(), vision.points_seen_sorted());
+    }
+
+    #[test]
+    fn test_to_local_and_to_world() {
+        let eye = Point(-7, 12);
+        let args = make_args(eye, Point::default(), |_: Point| 0);
+        let mut vision = Vision::new(5);
+        vision.compute(&args);
+
+        assert_eq!(vision.to_local(eye), Point(5, 5));
+        assert_eq!(vision.to_world(Point(0, 0)), eye - Point(5, 5));
+        for p in [Point(0, 0), Point(-9, 10), Point(-2, 17), Point(100, -100)] {
+            assert_eq!(vision.to_world(vision.to_local(p)), p);
+            assert_eq!(vision.to_local(vision.to_world(p)), p);
+        }
+        for &p in vision.get_points_seen() {
+            assert!(vision.visibility.contains(vision.to_local(p)));
+        }
+    }
+
+    #[test]
+    fn test_paint_decay() {
+        let opacity_lookup = |_: Point| 0;
+        let dir = Point::default();
+        let mut trail = Matrix::new(Point(10, 1), 0u8);
+        let mut vision = Vision::new(1);
+
+        for x in [1, 2, 3] {
+            let args = make_args(Point(x, 0), dir, opacity_lookup);
+            vision.compute(&args);
+            vision.paint_decay(&mut trail, 200, 100);
+        }
+        assert_eq!(trail.data, [0, 155, 255, 255, 200, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_union_seen() {
+        // Two rooms joined by a door at (5, 2); the wall is at x = 5.
+        let opacity_lookup = |p: Point| if p.0 == 5 && p.1 != 2 { INITIAL_VISIBILITY } else { 0 };
+        let dir = Point::default();
+        let a = make_args(Point(2, 2), dir, opacity_lookup);
+        let b = make_args(Point(8, 0), dir, opacity_lookup);
+
+        let (mut va, mut vb) = (Vision::new(4), Vision::new(4));
+        va.compute(&a);
+        vb.compute(&b);
+
+        let union = union_seen(&va, &vb);
+        let set: HashSet<_> = union.iter().copied().collect();
+        assert_eq!(set.len(), union.len());
+        for &p in va.get_points_seen().iter().chain(vb.get_points_seen()) {
+            assert!(set.contains(&p));
+        }
+        let overlap = va.get_points_seen().iter().filter(|p| vb.get_visibility_at(**p) >= 0);
+        let total = va.get_points_seen().len() + vb.get_points_seen().len();
+        assert_eq!(union.len(), total - overlap.count());
+
+        assert_eq!(max_visibility_at(&va, &vb, Point(2, 2)), INITIAL_VISIBILITY);
+        assert_eq!(max_visibility_at(&va, &vb, Point(8, 0)), INITIAL_VISIBILITY);
+        assert_eq!(max_visibility_at(&va, &vb, Point(20, 20)), -1);
+    }
+
+    #[test]
+    fn test_wall_bleed() {
+        // A wall two tiles thick at x = 2 and x = 3.
+        let opacity_lookup = |p: Point| if p.0 == 2 || p.0 == 3 { INITIAL_VISIBILITY } else { 0 };
+        let mut args = make_args(Point(0, 0), Point::default(), opacity_lookup);
+
+        let mut vision = Vision::new(6);
+        vision.compute(&args);
+        let row = |v: &Vision| (1..5).map(|x| v.get_visibility_at(Point(x, 0))).collect::<Vec<_>>();
+        assert_eq!(row(&vision), [100, 0, -1, -1]);
+
+        args.options.wall_bleed = 10;
+        vision.compute(&args);
+        assert_eq!(row(&vision), [100, 0, 10, -1]);
+        assert_eq!(vision.get_visibility_at(Point(3, 3)), 10);
+        assert_eq!(vision.get_visibility_at(Point(4, 1)), -1);
+
+        // Bleed stays within the radius under the vision's metric.
+        let builder = VisionBuilder::new().radius(4).metric(RadiusMetric::Taxicab);
+        let mut vision = builder.build().unwrap();
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), 10);
+        assert_eq!(vision.get_visibility_at(Point(3, 2)), -1);
+    }
+
+    #[test]
+    fn test_points_seen_sorted() {
+        // A fresh vision and one reused after a targeted cast agree once sorted.
+        let opacity_lookup = |p: Point| if p == Point(2, 1) { INITIAL_VISIBILITY } else { 0 };
+        let (eye, radius) = (Point(0, 0), 5);
+        let mut a = Vision::new(radius);
+        let mut b = Vision::new(radius);
+        a.compute(&make_args(eye, Point::default(), opacity_lookup));
+        b.can_see(&make_args(eye, Point::default(), opacity_lookup), Point(1, 1));
+        b.compute(&make_args(eye, Point::default(), opacity_lookup));
+
+        let sorted = a.points_seen_sorted();
+        assert_eq!(sorted, b.points_seen_sorted());
+        assert_eq!(sorted.len(), a.get_points_seen().len());
+        assert!(sorted.windows(2).all(|w| (w[0].1, w[0].0) < (w[1].1, w[1].0)));
+    }
+
+    #[test]
+    fn test_compute_tagged() {
+        const GLASS: u8 = 1;
+        let window = Point(2, 0);
+        let wall = |p: Point| p.0 == 2 && p != window;
+        let opacity_lookup = |p: Point| if wall(p) { INITIAL_VISIBILITY } else { 0 };
+        let tag = |p: Point| if p == window { GLASS } else { 0 };
+        let args = make_args(Point(0, 0), Point::default(), opacity_lookup);
+
+        let mut vision = Vision::new(4);
+        vision.compute_tagged(&args, tag);
+        assert_eq!(vision.tag_at(window), Some(GLASS));
+        assert_eq!(vision.tag_at(Point(1, 0)), Some(0));
+        assert_eq!(vision.tag_at(Point(3, 0)), Some(0));
+        assert_eq!(vision.tag_at(Point(3, 3)), None);
+
+        vision.compute(&args);
+        assert_eq!(vision.tag_at(window), None);
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        // Enclosed in rock, except for one open tile east of the eye.
+        let open = |p: Point| p == Point(0, 0) || p == Point(1, 0);
+        let opacity_lookup = |p: Point| if open(p) { 0 } else { INITIAL_VISIBILITY };
+        let args = make_args(Point(0, 0), Point::default(), opacity_lookup);
+
+        let mut vision = Vision::new(6);
+        assert_eq!(vision.bounding_box(), None);
+        vision.compute(&args);
+        assert_eq!(vision.bounding_box(), Some((Point(-1, -1), Point(2, 1))));
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), -1);
+    }
+
+    #[test]
+    fn test_multiplicative_falloff() {
+        let loss = VISIBILITY_LOSSES[6];
+        let opacity_lookup = |p: Point| if p.1 == 0 { loss } else { INITIAL_VISIBILITY };
+        let mut args = make_args(Point(0, 0), Point::default(), opacity_lookup);
+
+        let mut vision = Vision::new(12);
+        let mut corridor = |args: &VisionArgs<_>| {
+            vision.compute(args);
+            (1..11).map(|x| vision.get_visibility_at(Point(x, 0))).collect::<Vec<_>>()
+        };
+        let subtractive = corridor(&args);
+        args.options.falloff = Falloff::Multiplicative(INITIAL_VISIBILITY as f32);
+        let multiplicative = corridor(&args);
+
+        assert_eq!(subtractive, [85, 70, 55, 40, 25, 10, 0, -1, -1, -1]);
+        assert_eq!(multiplicative, [85, 72, 61, 51, 43, 36, 30, 25, 21, 17]);
+
+        // Walls still block under multiplicative falloff.
+        let opacity_lookup = |p: Point| if p.0 == 2 { INITIAL_VISIBILITY } else { 0 };
+        let mut args = make_args(Point(0, 0), Point::default(), opacity_lookup);
+        args.options.falloff = Falloff::Multiplicative(INITIAL_VISIBILITY as f32);
+        let mut vision = Vision::new(4);
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(2, 0)), 0);
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), -1);
+
+        // Even at scales well above the wall's opacity.
+        args.options.falloff = Falloff::Multiplicative(4.0 * INITIAL_VISIBILITY as f32);
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(1, 0)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(2, 0)), 0);
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), -1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multiplicative_falloff_zero_scale() {
+        let mut args = make_args(Point(0, 0), Point::default(), |_: Point| 0);
+        args.options.falloff = Falloff::Multiplicative(0.0);
+        Vision::new(4).compute(&args);
+    }
+
+    #[test]
+    fn test_compute_fog() {
+        use VisibilityState::*;
+        let opacity_lookup = |_: Point| 0;
+        let mut fog = Matrix::new(Point(8, 1), Unknown);
+        let mut vision = Vision::new(1);
+
+        vision.compute_fog(&make_args(Point(1, 0), Point::default(), opacity_lookup), &mut fog);
+        let (r, v, u) = (Remembered, Visible, Unknown);
+        assert_eq!(fog.data, [v, v, v, u, u, u, u, u]);
+
+        vision.compute_fog(&make_args(Point(4, 0), Point::default(), opacity_lookup), &mut fog);
+        assert_eq!(fog.data, [r, r, r, v, v, v, u, u]);
+
+        vision.compute_fog(&make_args(Point(5, 0), Point::default(), opacity_lookup), &mut fog);
+        assert_eq!(fog.data, [r, r, r, r, v, v, v, u]);
+    }
+
+    #[test]
+    fn test_valid_region() {
+        let (eye, map) = generate_fov_input();
+        let region = (eye - Point(3, 5), eye + Point(6, 2));
+        let inside = |p: Point| in_region(region, p);
+
+        let queried = std::cell::RefCell::new(vec![]);
+        let opacity_lookup = |p: Point| {
+            queried.borrow_mut().push(p);
+            if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 }
+        };
+        let mut args = make_args(eye, Point::default(), opacity_lookup);
+        args.options.wall_bleed = 1;
+        let mut expected = Vision::new(10);
+        expected.compute(&args);
+
+        queried.borrow_mut().clear();
+        args.options.valid_region = Some(region);
+        let mut vision = Vision::new(10);
+        vision.compute(&args);
+
+        assert!(!queried.borrow().is_empty());
+        assert!(queried.borrow().iter().all(|&p| inside(p)));
+        assert!(vision.get_points_seen().iter().all(|&p| inside(p)));
+        for &p in expected.get_points_seen().iter().filter(|&&p| inside(p)) {
+            assert_eq!(vision.get_visibility_at(p), expected.get_visibility_at(p));
+        }
+    }
+
+    #[test]
+    fn test_terminations() {
+        let run = |walls: &[Point]| {
+            let opacity_lookup = |p: Point| if walls.contains(&p) { INITIAL_VISIBILITY } else { 0 };
+            let args = make_args(Point(0, 0), Point::default(), opacity_lookup);
+            let mut result = vec![];
+            let mut vision = Vision::new(5);
+            vision.compute_with_terminations(&args, |p, _| result.push(p));
+            result
+        };
+        assert_eq!(run(&[]), []);
+        assert_eq!(run(&[Point(2, 1)]), [Point(2, 1)]);
+        assert_eq!(run(&[Point(3, -1), Point(3, 0), Point(3, 1)]), [Point(3, -1)]);
+        assert_eq!(run(&[Point(3, -1), Point(-3, 1)]).len(), 2);
+    }
+
+    #[test]
+    fn test_translation_invariance() {
+        let (eye, map) = generate_fov_input();
+        let opacity = |p: Point| match map.get(p) {
+            '#' => INITIAL_VISIBILITY,
+            ',' => VISIBILITY_LOSS,
+            _ => 0,
+        };
+
+        // Reuse one Vision throughout, so stale state would show up as a diff.
+        let mut vision = Vision::new(15);
+        for dir in [Point::default(), Point(3, -1)] {
+            vision.compute(&make_args(eye, dir, opacity));
+            let expected: Vec<_> = vision.get_points_seen().iter()
+                .map(|&p| (p - eye, vision.get_visibility_at(p))).collect();
+
+            for shift in [Point(1, 0), Point(-37, 5), Point(1000, -2000), Point(0, 0)] {
+                let shifted = |p: Point| opacity(p - shift);
+                vision.compute(&make_args(eye + shift, dir, shifted));
+                let actual: Vec<_> = vision.get_points_seen().iter()
+                    .map(|&p| (p - eye - shift, vision.get_visibility_at(p))).collect();
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_sight_along() {
+        // An L-shaped corridor: east from (1, 1) to (7, 1), south to (1, 4).
+        let walls = |p: Point| {
+            let open = (p.1 == 1 && 1 <= p.0 && p.0 <= 7) || (p.0 == 1 && 1 <= p.1 && p.1 <= 4);
+            if open { 0 } else { INITIAL_VISIBILITY }
+        };
+        let mut vision = Vision::new(10);
+        vision.compute(&make_args(Point(1, 1), Point::default(), walls));
+        assert_eq!(vision.max_sight_along(Point(1, 0)), Some(Point(7, 1)));
+        assert_eq!(vision.max_sight_along(Point(0, 3)), Some(Point(1, 4)));
+        assert_eq!(vision.max_sight_along(Point(1, 1)), None);
+        assert_eq!(vision.max_sight_along(Point(-1, 0)), None);
+        assert_eq!(vision.max_sight_along(Point(0, 0)), None);
+    }
+
+    #[test]
+    fn test_ray_profile() {
+        // Grass fades the light, and a wall at (5, 0) shadows what's behind.
+        let mut map = Matrix::new(Point(9, 1), INITIAL_VISIBILITY);
+        for x in 0..9 { map.set(Point(x, 0), if x == 5 { INITIAL_VISIBILITY } else { 10 }); }
+
+        let mut vision = Vision::new(10);
+        vision.compute(&make_args(Point(0, 0), Point::default(), |p| map.get(p)));
+        let values: Vec<_> = vision.ray_profile(Point(2, 0), Point(7, 0)).into_iter()
+            .map(|(p, v)| { assert_eq!(p.1, 0); v }).collect();
+        assert_eq!(values, vec![80, 70, 60, 0, -1, -1]);
+    }
+
+    #[test]
+    fn test_perimeter() {
+        let mut vision = Vision::new(6);
+        vision.compute(&make_args(Point(0, 0), Point::default(), |_| 0));
+        let open = vision.perimeter();
+        assert_eq!(open, 13 * 4);
+
+        // In a corridor, only the corridor's sides and far ends are exposed.
+        vision.compute(&make_args(Point(0, 0), Point::default(), |p: Point| {
+            if p.1 == 0 { 0 } else { INITIAL_VISIBILITY }
+        }));
+        assert_eq!(vision.perimeter(), 13 * 2 + 2);
+        assert!(open > vision.perimeter());
+    }
+
+    #[test]
+    fn test_transparent_override() {
+        let wall = |p: Point| if p == Point(3, 0) { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(6);
+        let mut args = make_args(Point(0, 0), Point::default(), wall);
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), 0);
+        assert_eq!(vision.get_visibility_at(Point(4, 0)), -1);
+
+        args.options.transparent_override = vec![Point(3, 0), Point(-2, 5)];
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(4, 0)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(6, 0)), INITIAL_VISIBILITY);
+    }
+
+    #[test]
+    fn test_compute_with_light_dir() {
+        let eye = Point(4, 4);
+        let mut dirs = Matrix::new(Point(9, 9), Point::default());
+        let mut vision = Vision::new(3);
+        vision.compute_with_light_dir(&make_args(eye, Point::default(), |_| 0), &mut dirs);
+        assert_eq!(dirs.get(Point(4, 5)), Point(0, 1));
+        assert_eq!(dirs.get(Point(4, 7)), Point(0, 3));
+        assert_eq!(dirs.get(Point(2, 3)), Point(-2, -1));
+        assert_eq!(dirs.get(eye), Point::default());
+    }
+
+    #[test]
+    fn test_recovers_from_panicking_lookup() {
+        let (eye, map) = generate_fov_input();
+        let lookup = |p: Point| if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 };
+        let args = make_args(eye, Point::default(), lookup);
+        let mut expected = Vision::new(20);
+        expected.compute(&args);
+
+        // Panic after a few lookups, leaving the seen set sparse, so the next
+        // clear takes the sparse path and relies on points_seen being complete.
+        let calls = std::cell::Cell::new(0);
+        let mut vision = Vision::new(20);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vision.compute(&make_args(Point(5, 5), Point::default(), |p: Point| {
+                calls.set(calls.get() + 1);
+                if calls.get() > 40 { panic!("lookup failed"); }
+                lookup(p)
+            }));
+        }));
+        assert!(result.is_err());
+        assert!(16 * vision.get_points_seen().len() <= (41 * 41) as usize);
+
+        vision.compute(&args);
+        assert_eq!(vision.points_seen_sorted(), expected.points_seen_sorted());
+        for &p in expected.get_points_seen() {
+            assert_eq!(vision.get_visibility_at(p), expected.get_visibility_at(p));
+        }
+    }
+
+    #[test]
+    fn test_see_over_height() {
+        // Opacity doubles as height: a short wall at x = 2, a tall one at x = 5.
+        let (short, tall) = (INITIAL_VISIBILITY, 2 * INITIAL_VISIBILITY);
+        let lookup = |p: Point| match p.0 { 2 => short, 5 => tall, _ => 0 };
+        let mut vision = Vision::new(8);
+        let mut args = make_args(Point(0, 0), Point::default(), lookup);
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(2, 0)), 0);
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), -1);
+
+        args.options.see_over_height = short;
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(2, 0)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(4, 0)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(5, 0)), 0);
+        assert_eq!(vision.get_visibility_at(Point(6, 0)), -1);
+    }
+
+    #[test]
+    fn test_reachable_visible() {
+        // A chasm at x = 3 separates the eye's floor from a visible ledge.
+        let mut passable = Matrix::new(Point(7, 3), true);
+        for y in 0..3 { passable.set(Point(3, y), false); }
+        let mut vision = Vision::new(6);
+        vision.compute(&make_args(Point(1, 1), Point::default(), |_| 0));
+        assert_eq!(vision.get_visibility_at(Point(5, 1)), INITIAL_VISIBILITY);
+
+        let mut reachable = vision.reachable_visible(&passable);
+        reachable.sort_unstable_by_key(|p| (p.1, p.0));
+        let expected: Vec<_> = (0..3).flat_map(|y| (0..3).map(move |x| Point(x, y))).collect();
+        assert_eq!(reachable, expected);
+    }
+
+    #[test]
+    fn test_layered() {
+        let floor = |p: Point| if p.0 == 2 { VISIBILITY_LOSS } else { 0 };
+        let walls = |p: Point| if p == Point(4, 0) { INITIAL_VISIBILITY } else { 0 };
+        let layers: [&dyn Fn(Point) -> i32; 2] = [&floor, &walls];
+        let mut vision = Vision::new(6);
+        vision.compute(&VisionArgs::new(Point(0, 0), Layered(layers.to_vec())));
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), INITIAL_VISIBILITY - VISIBILITY_LOSS);
+        assert_eq!(vision.get_visibility_at(Point(4, 0)), 0);
+        assert_eq!(vision.get_visibility_at(Point(5, 0)), -1);
+
+        // Without the wall layer, the beam continues past (4, 0).
+        vision.compute(&VisionArgs::new(Point(0, 0), Layered(vec![floor])));
+        assert_eq!(vision.get_visibility_at(Point(5, 0)), INITIAL_VISIBILITY - VISIBILITY_LOSS);
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let mut vision = Vision::new(10);
+        vision.compute(&make_args(Point(0, 0), Point::default(), |_| 0));
+        let actual = vision.get_points_seen().len();
+        let estimate = Vision::estimate_cost(10, Point::default());
+        assert!(estimate.abs_diff(actual) * 20 < actual);
+
+        let directional = Vision::estimate_cost(10, Point(1, 0));
+        assert!(directional < estimate);
+        assert!(Vision::estimate_cost(9, Point(1, 0)) < directional);
+        assert_eq!(Vision::estimate_cost(0, Point::default()), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_compute_validated() {
+        let (eye, map) = generate_fov_input();
+        let mut vision = Vision::new(12);
+        vision.compute_validated(&make_args(eye, Point::default(), |p: Point| {
+            if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 }
+        }));
+
+        // Denser maps with pillars, diagonal gaps, and grazing corners.
+        let mut rng = StdRng::seed_from_u64(23);
+        for density in [10, 25, 40] {
+            let mut walls = Matrix::new(Point(25, 25), true);
+            for i in 0..walls.data.len() { walls.data[i] = rng.random_range(0..100) < density; }
+            let eye = Point(12, 12);
+            walls.set(eye, false);
+            vision.compute_validated(&make_args(eye, Point::default(), |p: Point| {
+                if walls.get(p) { INITIAL_VISIBILITY } else { 0 }
+            }));
+        }
+
+        // Other metrics aren't checked, since the brute force uses the disc.
+        let metric = RadiusMetric::Chebyshev;
+        let mut vision = VisionBuilder::new().radius(4).metric(metric).build().unwrap();
+        vision.compute_validated(&make_args(eye, Point::default(), |_| 0));
+    }
+
+    #[test]
+    fn test_compute_faded_radius() {
+        let args = make_args(Point(0, 0), Point::default(), |_| 0);
+        let mut vision = Vision::new(8);
+        vision.compute_faded_radius(&args, 4.5);
+        assert_eq!(vision.get_visibility_at(Point(4, 0)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(5, 0)), INITIAL_VISIBILITY / 2);
+        assert_eq!(vision.get_visibility_at(Point(6, 0)), -1);
+        assert!(vision.get_points_seen().iter().all(|&p| vision.get_visibility_at(p) >= 0));
+
+        // Integer radii match a plain cast with that radius.
+        vision.compute_faded_radius(&args, 4.0);
+        let mut expected = Vision::new(4);
+        expected.compute(&args);
+        assert_eq!(vision.points_seen_sorted(), expected.points_seen_sorted());
+
+        // The next full cast restores everything, including the faded ring.
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(8, 0)), INITIAL_VISIBILITY);
+
+        // Under another metric, the rings follow that metric.
+        let builder = VisionBuilder::new().radius(8).metric(RadiusMetric::Chebyshev);
+        let mut vision = builder.build().unwrap();
+        vision.compute_faded_radius(&args, 4.5);
+        assert_eq!(vision.get_visibility_at(Point(4, 4)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(5, 5)), INITIAL_VISIBILITY / 2);
+        assert_eq!(vision.get_visibility_at(Point(6, 1)), -1);
+    }
+
+    #[test]
+    fn test_transform_apply() {
+        let p = Point(3, -2);
+        for transform in TRANSFORMS {
+            assert_eq!(transform.apply(p), transform * p);
+        }
+        assert_eq!(Transform::ROT_90.apply(Point(1, 0)), Point(0, 1));
+        assert_eq!(Transform::ROT_180.apply(p), Point(-3, 2));
+        assert_eq!(Transform::ROT_270.apply(Transform::ROT_90.apply(p)), p);
+        assert_eq!(Transform::IDENTITY.apply(p), p);
+
+        // Point::transform is the same, in method form.
+        for transform in TRANSFORMS {
+            assert_eq!(p.transform(transform), transform.apply(p));
+        }
+        assert_eq!(Point(1, 0).transform(Transform::ROT_90), Point(0, 1));
+        assert_eq!(p.transform(Transform::ROT_90).transform(Transform::ROT_270), p);
+    }
+
+    #[test]
+    fn test_seen_within() {
+        let eye = Point(5, 5);
+        let mut vision = Vision::new(10);
+        vision.compute(&make_args(eye, Point::default(), |_| 0));
+        let near: Vec<_> = vision.seen_within(3, RadiusMetric::Euclidean).collect();
+        assert!(near.iter().all(|&(p, v)| vision.get_points_seen().contains(&p) && v >= 0));
+        assert!(near.iter().all(|&(p, _)| (p - eye).len_l2_squared() <= 12));
+        assert!(near.iter().any(|&(p, _)| p == Point(8, 6)));
+
+        // A radius-3 cast sees exactly the same tiles in the open.
+        let mut small = Vision::new(3);
+        small.compute(&make_args(eye, Point::default(), |_| 0));
+        assert_eq!(near.len(), small.get_points_seen().len());
+        assert_eq!(vision.seen_within(2, RadiusMetric::Chebyshev).count(), 25);
     }
 
     #[test]
-    fn test_semitransparent_walls() {
-        test_fov(&[
-            ".....,...,.,.,.",
-            ".....,.@.,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-            ".....,...,.,.,.",
-        ], &[
-            ".....,...,.,.,%",
-            ".....,.@.,.,.,%",
-            ".....,...,.,.,%",
-            ".....,...,.,%%%",
-            ".....,...,.,%%%",
-            ".....,...,.,%%%",
-            ".....,...,.,%%%",
-            "....%,...,%,%%%",
-            "....%,...,%%%%%",
-            "...%%,...,%%%%%",
-            "..%%%,...,%%%%%",
-            "..%%%,...,%%%%%",
-            ".%%%%,...,%%%%%",
-            "%%%%%,...,%%%%%",
-            "%%%%%,...,%%%%%",
-            "%%%%%,...,%%%%%",
-            "%%%%%,...,%%%%%",
-        ]);
+    fn test_reveal_radius() {
+        let wall = |p: Point| if p == Point(2, 2) { INITIAL_VISIBILITY } else { 0 };
+        let mut args = make_args(Point(0, 0), Point::default(), wall);
+        args.options.reveal_radius = Some(6);
+        let mut vision = Vision::new(3);
+        vision.compute(&args);
+
+        let mut explored = Matrix::new(Point(16, 16), false);
+        vision.mark_explored(&mut explored);
+        let count = explored.data.iter().filter(|&&x| x).count();
+        assert!(count > vision.get_points_seen().len());
+        assert!(explored.get(Point(5, 0)));
+        assert_eq!(vision.get_visibility_at(Point(5, 0)), -1);
+        assert!(!explored.get(Point(4, 4)));
+
+        // Without a reveal radius, only seen tiles are revealed.
+        args.options.reveal_radius = None;
+        vision.compute(&args);
+        assert_eq!(vision.get_points_revealed(), vision.get_points_seen());
+
+        // The revealer casts with the caller's metric and options.
+        let mut args = make_args(Point(0, 0), Point(1, 0), |_| 0);
+        args.options = CastOptions { reveal_radius: Some(6), ..Default::default() };
+        let builder = VisionBuilder::new().radius(3).metric(RadiusMetric::Chebyshev);
+        let mut vision = builder.build().unwrap();
+        vision.compute(&args);
+        assert!(vision.get_points_revealed().contains(&Point(6, 6)));
+
+        args.options.exclude_behind = true;
+        vision.compute(&args);
+        let revealed = vision.get_points_revealed();
+        assert!(revealed.contains(&Point(6, 6)));
+        assert!(revealed.iter().all(|p| p.0 >= 0));
     }
 
     #[test]
-    fn test_directional_s() {
-        test_fov(&[
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            "......@......",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            "......X......",
-        ], &[
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%@%%%%%%",
-            "%%%%.....%%%%",
-            "%%.........%%",
-            "%...........%",
-            ".............",
-            ".............",
-            "......X......",
-        ]);
+    fn test_regions() {
+        let mut vision = Vision::new(8);
+        vision.compute(&make_args(Point(0, 0), Point::default(), |_| VISIBILITY_LOSS));
+        let regions = vision.regions();
+        let mut bands: Vec<_> = regions.iter().map(|(v, r)| (*v, r.len())).collect();
+        bands.sort_unstable();
+
+        // Light fades in bands: the eye, its neighbors, diagonals, then the
+        // tiles two steps out, all enclosed by a connected ring of darkness.
+        let expected = [(0, 24), (10, 1), (10, 1), (10, 1), (10, 1), (33, 1), (33, 1), (33, 1),
+                        (33, 1), (55, 1), (55, 1), (55, 1), (55, 1), (100, 1)];
+        assert_eq!(bands, expected);
+        let total: usize = regions.iter().map(|(_, r)| r.len()).sum();
+        assert_eq!(total, vision.get_points_seen().len());
+        for (visibility, region) in &regions {
+            assert!(region.iter().all(|&p| vision.get_visibility_at(p) == *visibility));
+        }
     }
 
     #[test]
-    fn test_directional_sw() {
-        test_fov(&[
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            "......@......",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            "X............",
-        ], &[
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            ".%%%%%%%%%%%%",
-            ".....%%%%%%%%",
-            "......@%%%%%%",
-            ".......%%%%%%",
-            "........%%%%%",
-            "........%%%%%",
-            "........%%%%%",
-            "........%%%%%",
-            "X........%%%%",
-        ]);
+    fn test_compute_cardinals() {
+        let (eye, map) = generate_fov_input();
+        let lookup = |p: Point| if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(10);
+        let options = CastOptions::default();
+        let results = vision.compute_cardinals(eye, lookup, INITIAL_VISIBILITY, &options);
+
+        let dirs = [Point(0, -1), Point(1, 0), Point(0, 1), Point(-1, 0)];
+        for (result, dir) in results.iter().zip(dirs) {
+            let mut expected = Vision::new(10);
+            expected.compute(&make_args(eye, dir, lookup));
+            assert_eq!(result, expected.get_points_seen());
+            assert!(result.iter().all(|&p| (p - eye).dot(dir) >= 0));
+        }
+
+        // Options apply to every facing.
+        let mut args = make_args(eye, Point::default(), lookup);
+        args.options.exclude_behind = true;
+        let results = vision.compute_cardinals(eye, lookup, INITIAL_VISIBILITY, &args.options);
+        for (result, dir) in results.iter().zip(dirs) {
+            args.dir = dir;
+            let mut expected = Vision::new(10);
+            expected.compute(&args);
+            assert_eq!(result, expected.get_points_seen());
+        }
     }
 
     #[test]
-    fn test_directional_ssw() {
-        test_fov(&[
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            "......@......",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            ".............",
-            "...X.........",
-        ], &[
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "%%%%%%%%%%%%%",
-            "......@%%%%%%",
-            "........%%%%%",
-            "........%%%%%",
-            ".........%%%%",
-            "..........%%%",
-            "..........%%%",
-            "...X.......%%",
-        ]);
+    fn test_seen_by_distance() {
+        let (eye, map) = generate_fov_input();
+        let lookup = |p: Point| if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(10);
+        vision.compute(&make_args(eye, Point::default(), lookup));
+
+        let sorted = vision.seen_by_distance();
+        assert_eq!(sorted.len(), vision.get_points_seen().len());
+        assert_eq!(sorted[0], (eye, INITIAL_VISIBILITY));
+        let distances: Vec<_> = sorted.iter().map(|&(p, _)| (p - eye).len_l2_squared()).collect();
+        assert!(distances.windows(2).all(|x| x[0] <= x[1]));
+        assert!(sorted.iter().all(|&(p, v)| vision.get_visibility_at(p) == v));
+    }
+
+    #[test]
+    fn test_min_range_width() {
+        // A grid of pillars splits the light into many thin beams.
+        let lookup = |p: Point| {
+            let Point(x, y) = p;
+            let pillar = x.abs() > 2 && y.abs() > 2 && x % 3 == 0 && y % 3 == 0;
+            if pillar { INITIAL_VISIBILITY } else { 0 }
+        };
+        let mut args = make_args(Point(0, 0), Point::default(), lookup);
+
+        // Runs the cast a row at a time to find the most beams in flight.
+        let cast = |vision: &mut Vision, args: &VisionArgs<_>| {
+            vision.clear(args.eye, args.initial_visibility);
+            vision.seed_ranges(args.dir, None, args.initial_visibility);
+            let mut most = 0;
+            for depth in 1..=vision.radius {
+                let fetch = lookup_span(&args.opacity_lookup);
+                vision.execute(&args.options, args.eye, depth, fetch, |_, _| {});
+                most = std::cmp::max(most, vision.prev.items.len());
+            }
+            most
+        };
+        let (mut exact, mut rough) = (Vision::new(20), Vision::new(20));
+        let exact_beams = cast(&mut exact, &args);
+        args.options.min_range_width = 100;
+        let rough_beams = cast(&mut rough, &args);
+
+        assert!(2 * rough_beams <= exact_beams);
+        let (exact_seen, rough_seen) = (exact.get_points_seen(), rough.get_points_seen());
+        assert!(rough_seen.iter().all(|&p| exact.get_visibility_at(p) >= 0));
+        assert!(4 * rough_seen.len() >= 3 * exact_seen.len());
+    }
+
+    #[test]
+    fn test_brightness_bilinear() {
+        let wall = |p: Point| if p.0 == 3 { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(6);
+        vision.compute(&make_args(Point(0, 0), Point::default(), wall));
+        assert_eq!(vision.brightness_at(Point(2, 0)), 1.0);
+        assert_eq!(vision.brightness_at(Point(3, 0)), 0.0);
+        assert_eq!(vision.brightness_at(Point(4, 0)), 0.0);
+
+        assert_eq!(vision.brightness_bilinear((2.0, 0.0)), 1.0);
+        assert_eq!(vision.brightness_bilinear((2.5, 0.0)), 0.5);
+        assert_eq!(vision.brightness_bilinear((2.75, 0.5)), 0.25);
+        assert_eq!(vision.brightness_bilinear((3.5, -1.0)), 0.0);
+    }
+
+    #[test]
+    fn test_compute_from_slit() {
+        // A wall along x = 0, with the slit at (0, 5) opening into a room.
+        let wall = |p: Point| if p.0 == 0 && p.1 != 5 { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(8);
+        let half_angle = std::f64::consts::FRAC_PI_6;
+        let slit = |vision: &mut Vision, initial_visibility: i32, options: &CastOptions| {
+            vision.compute_from_slit(
+                Point(0, 5), Point(1, 0), half_angle, wall, initial_visibility, options);
+        };
+        slit(&mut vision, INITIAL_VISIBILITY, &CastOptions::default());
+
+        assert!(vision.get_visibility_at(Point(6, 5)) > 0);
+        assert!(vision.get_visibility_at(Point(6, 7)) > 0);
+        assert!(vision.get_visibility_at(Point(6, 3)) > 0);
+        assert_eq!(vision.get_visibility_at(Point(2, 8)), -1);
+        assert!(vision.get_points_seen().iter().all(|&p| p.0 >= 0));
+
+        // The cone spreads evenly to either side of the normal.
+        let seen = |vision: &Vision, y: i32| vision.get_visibility_at(Point(6, 5 + y)) >= 0;
+        assert!((1..8).all(|y| seen(&vision, y) == seen(&vision, -y)));
+
+        // The initial visibility and options apply, here dimming the light and
+        // cutting it off past x = 4.
+        let valid_region = Some((Point(0, 0), Point(4, 10)));
+        let options = CastOptions { valid_region, ..Default::default() };
+        slit(&mut vision, 60, &options);
+        assert_eq!(vision.get_visibility_at(Point(0, 5)), 60);
+        assert_eq!(vision.get_visibility_at(Point(3, 5)), 60);
+        assert_eq!(vision.get_visibility_at(Point(6, 5)), -1);
+    }
+
+    #[test]
+    fn test_light_diff() {
+        let size = Point(20, 10);
+        let (mut va, mut vb) = (Vision::new(3), Vision::new(3));
+        va.compute(&make_args(Point(4, 4), Point::default(), |_| 0));
+        vb.compute(&make_args(Point(14, 4), Point::default(), |_| 0));
+        let mut old = Matrix::new(size, 0);
+        va.add_to_light(&mut old);
+        vb.add_to_light(&mut old);
+        assert!(light_diff(&old, &old).is_empty());
+
+        // Moving one source only touches cells near its old and new positions.
+        let mut new = Matrix::new(size, 0);
+        va.add_to_light(&mut new);
+        vb.compute(&make_args(Point(15, 4), Point::default(), |_| 0));
+        vb.add_to_light(&mut new);
+        let diff = light_diff(&old, &new);
+        assert!(!diff.is_empty());
+        assert!(diff.len() <= 2 * vb.get_points_seen().len());
+        assert!(diff.iter().all(|&(p, v)| p.0 >= 11 && new.get(p) == v && old.get(p) != v));
+    }
+
+    #[test]
+    fn test_projection_offset() {
+        let (eye, map) = generate_fov_input();
+        let lookup = |p: Point| if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 };
+        let mut args = make_args(eye, Point::default(), lookup);
+        let mut expected = Vision::new(8);
+        expected.compute(&args);
+
+        let shift = Point(100, -3);
+        args.options.projection_offset = shift;
+        let mut vision = Vision::new(8);
+        vision.compute(&args);
+        let shifted: Vec<_> = expected.get_points_seen().iter().map(|&p| p + shift).collect();
+        assert_eq!(vision.get_points_seen(), shifted);
+        for &p in expected.get_points_seen() {
+            assert_eq!(vision.get_visibility_at(p + shift), expected.get_visibility_at(p));
+        }
+        assert_eq!(vision.eye(), eye + shift);
+        for &p in expected.get_points_seen().iter().take(20) {
+            assert!(vision.can_see(&args, p));
+        }
+    }
+
+    #[test]
+    fn test_visible_centroid() {
+        let eye = Point(5, 5);
+        let mut vision = Vision::new(8);
+        vision.compute(&make_args(eye, Point::default(), |_| 0));
+        assert_eq!(vision.visible_centroid(), Some(eye));
+
+        vision.compute(&make_args(eye, Point(1, 0), |_| 0));
+        let centroid = vision.visible_centroid().unwrap();
+        assert!(centroid.0 > eye.0 + 2);
+        assert_eq!(centroid.1, eye.1);
+
+        assert_eq!(Vision::new(8).visible_centroid(), None);
+    }
+
+    #[test]
+    fn test_residual_on_equal() {
+        // The beam reaches (2, 0) with exactly the visibility that tile blocks.
+        let lookup = |p: Point| match p {
+            Point(1, 0) => VISIBILITY_LOSS,
+            Point(2, 0) => INITIAL_VISIBILITY - VISIBILITY_LOSS,
+            _ => 0,
+        };
+        let mut args = make_args(Point(0, 0), Point::default(), lookup);
+        let mut vision = Vision::new(6);
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(2, 0)), 0);
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), -1);
+
+        args.options.residual_on_equal = true;
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(2, 0)), 1);
+        assert_eq!(vision.get_visibility_at(Point(3, 0)), 1);
+        assert_eq!(vision.get_visibility_at(Point(6, 0)), 1);
+    }
+
+    #[test]
+    fn test_compute_mask() {
+        let (eye, map) = generate_fov_input();
+        let lookup = |p: Point| if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(10);
+        let mut mask = Matrix::new(map.size, 7);
+        let bounds = (Point(-5, 8), Point(30, 35));
+        vision.compute_mask(&make_args(eye, Point::default(), lookup), &mut mask, 0, 255, bounds);
+
+        for y in 0..map.size.1 {
+            for x in 0..map.size.0 {
+                let p = Point(x, y);
+                let inside = x <= 30 && (8..=35).contains(&y);
+                let seen = vision.get_visibility_at(p) >= 0;
+                let expected = if !inside { 7 } else if seen { 0 } else { 255 };
+                assert_eq!(mask.get(p), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compute_from_segment() {
+        let (eye, map) = generate_fov_input();
+        let lookup = |p: Point| if map.get(p) == '#' { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(8);
+        vision.compute(&make_args(eye, Point(1, 0), lookup));
+        let single = vision.points_seen_sorted();
+
+        let (a, b) = (eye - Point(0, 1), eye + Point(0, 1));
+        let mut segment = |options: &CastOptions| {
+            vision.compute_from_segment(a, b, Point(1, 0), lookup, INITIAL_VISIBILITY, options)
+        };
+        let lit = segment(&CastOptions::default());
+        assert!(single.iter().all(|p| lit.iter().any(|x| x.0 == *p)));
+        assert!(lit.len() > single.len() + 4);
+        assert!(lit.iter().all(|&(p, _)| p.0 >= eye.0));
+
+        // Each tile appears once, with its brightest visibility.
+        let mut points: Vec<_> = lit.iter().map(|x| x.0).collect();
+        points.sort_unstable_by_key(|p| (p.1, p.0));
+        points.dedup();
+        assert_eq!(points.len(), lit.len());
+        assert!(lit.contains(&(a, INITIAL_VISIBILITY)));
+
+        // Options apply to each cast, here widening the cone to a half-plane.
+        let options = CastOptions { exclude_behind: true, ..Default::default() };
+        let wide = segment(&options);
+        assert!(lit.iter().all(|x| wide.iter().any(|y| y.0 == x.0)));
+        assert!(wide.len() > lit.len());
+        assert!(wide.iter().all(|&(p, _)| p.0 >= eye.0));
+    }
+
+    #[test]
+    fn test_exclude_behind() {
+        let eye = Point(0, 0);
+        let mut args = make_args(eye, Point(1, 0), |_| 0);
+        let mut vision = Vision::new(6);
+        vision.compute(&args);
+        assert_eq!(vision.get_visibility_at(Point(0, 4)), -1);
+
+        args.options.exclude_behind = true;
+        vision.compute(&args);
+        assert!(vision.get_points_seen().iter().all(|&p| p.0 >= 0));
+        assert_eq!(vision.get_visibility_at(Point(0, 4)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(0, -4)), INITIAL_VISIBILITY);
+        assert_eq!(vision.get_visibility_at(Point(-1, 0)), -1);
+        assert!(vision.can_see(&args, Point(0, 4)));
+        assert!(!vision.can_see(&args, Point(-1, 2)));
+    }
+
+    #[test]
+    fn test_compute_result() {
+        let wall = |p: Point| if p.0 == 2 { INITIAL_VISIBILITY } else { 0 };
+        let mut vision = Vision::new(4);
+        let a = vision.compute_result(&make_args(Point(0, 0), Point::default(), wall));
+        let b = vision.compute_result(&make_args(Point(4, 0), Point::default(), wall));
+        assert!(vision.get_points_seen().is_empty());
+
+        assert_eq!(a.radius(), 4);
+        assert_eq!(a.get_visibility_at(Point(1, 0)), INITIAL_VISIBILITY);
+        assert_eq!(a.get_visibility_at(Point(3, 0)), -1);
+        assert_eq!(b.get_visibility_at(Point(1, 0)), -1);
+        assert_eq!(b.get_visibility_at(Point(3, 0)), INITIAL_VISIBILITY);
+        assert!(a.get_points_seen().iter().all(|&p| p.0 <= 2));
+        assert!(b.get_points_seen().iter().all(|&p| p.0 >= 2));
+
+        // The Vision still works normally after its results are moved out.
+        vision.compute(&make_args(Point(0, 0), Point::default(), wall));
+        assert_eq!(vision.points_seen_sorted().len(), a.get_points_seen().len());
+    }
+
+    #[test]
+    fn test_diffraction() {
+        // A wall along x = 3 for y <= 0, whose corner is at (3, 0).
+        let wall = |p: Point| if p.0 == 3 && p.1 <= 0 { INITIAL_VISIBILITY } else { 0 };
+        let mut args = make_args(Point(0, 0), Point::default(), wall);
+        let mut plain = Vision::new(8);
+        plain.compute(&args);
+
+        args.options.diffraction = 20;
+        let mut vision = Vision::new(8);
+        vision.compute(&args);
+        let extra: Vec<_> = vision.points_seen_sorted().into_iter()
+            .filter(|&p| plain.get_visibility_at(p) < 0).collect();
+        assert!(!extra.is_empty());
+        assert!(extra.iter().all(|&p| p.0 > 3 && vision.get_visibility_at(p) == 20));
+        assert!(plain.get_points_seen().iter().all(|&p| {
+            vision.get_visibility_at(p) == plain.get_visibility_at(p)
+        }));
     }
 
     fn generate_fov_input() -> (Point, Matrix<char>) {
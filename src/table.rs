@@ -0,0 +1,150 @@
+use crate::base::{Matrix, Point};
+use crate::shadowcast::{CastOptions, Vision, VisionArgs, INITIAL_VISIBILITY};
+
+//////////////////////////////////////////////////////////////////////////////
+
+// BitSet
+
+#[derive(Clone, Debug, Default)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn new(len: usize) -> Self {
+        Self { words: vec![0; len.div_ceil(64)] }
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        let Some(&word) = self.words.get(index / 64) else { return false; };
+        word & (1 << (index % 64)) != 0
+    }
+
+    pub fn insert(&mut self, index: usize) {
+        self.words[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|x| x.count_ones() as usize).sum()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+// VisibilityTable
+
+// Precomputed boolean FOV from every tile of a static map. Each origin's set
+// is indexed by the map's Matrix layout, so memory use is quadratic in the
+// map's area; tiles outside the map are never included.
+pub struct VisibilityTable {
+    size: Point,
+    sets: Vec<BitSet>,
+}
+
+impl VisibilityTable {
+    pub fn new(opacity: &Matrix<i32>, radius: i32) -> Self {
+        let size = opacity.size;
+        let cells = opacity.data.len();
+        let mut vision = Vision::new(radius);
+        let mut sets = Vec::with_capacity(cells);
+
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                let args = VisionArgs {
+                    eye: Point(x, y),
+                    dir: Point::default(),
+                    opacity_lookup: |p: Point| opacity.get(p),
+                    initial_visibility: INITIAL_VISIBILITY,
+                    options: CastOptions::default(),
+                };
+                vision.compute(&args);
+
+                let mut set = BitSet::new(cells);
+                for &point in vision.get_points_seen() {
+                    let Some(index) = opacity.index(point) else { continue; };
+                    set.insert(index);
+                }
+                sets.push(set);
+            }
+        }
+        Self { size, sets }
+    }
+
+    pub fn visible_from(&self, origin: Point) -> &BitSet {
+        let Some(index) = self.index(origin) else { panic!("origin out of bounds: {:?}", origin) };
+        &self.sets[index]
+    }
+
+    pub fn is_visible(&self, origin: Point, target: Point) -> bool {
+        let Some(index) = self.index(target) else { return false; };
+        self.visible_from(origin).contains(index)
+    }
+
+    // The flat index of a cell, computed in usize like Matrix::index.
+    fn index(&self, point: Point) -> Option<usize> {
+        let Point(x, y) = point;
+        if x < 0 || x >= self.size.0 || y < 0 || y >= self.size.1 { return None; }
+        Some(x as usize + y as usize * self.size.0 as usize)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_matches_compute() {
+        let rows = [
+            "..........",
+            "..#....#..",
+            "..#.......",
+            "......##..",
+            "..........",
+            "#...#.....",
+        ];
+        let size = Point(rows[0].len() as i32, rows.len() as i32);
+        let mut opacity = Matrix::new(size, INITIAL_VISIBILITY);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let value = if c == '#' { INITIAL_VISIBILITY } else { 0 };
+                opacity.set(Point(x as i32, y as i32), value);
+            }
+        }
+
+        let radius = 6;
+        let table = VisibilityTable::new(&opacity, radius);
+        let mut vision = Vision::new(radius);
+        for origin in [Point(0, 0), Point(4, 2), Point(9, 5), Point(5, 4)] {
+            let args = VisionArgs {
+                eye: origin,
+                dir: Point::default(),
+                opacity_lookup: |p: Point| opacity.get(p),
+                initial_visibility: INITIAL_VISIBILITY,
+                options: CastOptions::default(),
+            };
+            vision.compute(&args);
+
+            let mut count = 0;
+            for y in 0..size.1 {
+                for x in 0..size.0 {
+                    let p = Point(x, y);
+                    let expected = vision.get_visibility_at(p) >= 0;
+                    assert_eq!(table.is_visible(origin, p), expected);
+                    count += expected as usize;
+                }
+            }
+            assert_eq!(table.visible_from(origin).count(), count);
+            assert!(!table.is_visible(origin, Point(size.0, 0)));
+            assert!(!table.is_visible(origin, Point(-1, 0)));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_visible_from_out_of_bounds() {
+        let table = VisibilityTable::new(&Matrix::new(Point(2, 2), 0), 2);
+        table.visible_from(Point(2, 0));
+    }
+}